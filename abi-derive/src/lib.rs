@@ -0,0 +1,247 @@
+//! `#[derive(StorageData)]` for `zkwasm_rest_abi::StorageData`.
+//!
+//! Fields are stored in declaration order, each through its own `StorageData` implementation.
+//! Both `from_data` and `try_from_data` are generated, errors of `try_from_data` are prefixed with
+//! the name of the field that failed.
+//! The struct attribute `#[storage(version = N, migrate = "path")]` sets `StorageData::VERSION`
+//! and forwards `StorageData::migrate` to the function `path(version, u64data)`.
+//!
+//! The field attribute `#[storage(...)]` supports:
+//! * `skip`: the field is not stored and is restored with `Default::default()`
+//! * `option_flag = "field"`: an `Option` field stored without its own presence word, present
+//!   when the earlier integer field `field` is not zero (see `MarketInfo::bid`)
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Field, Fields, GenericArgument, Ident, Index, LitStr,
+    PathArguments, Type,
+};
+
+#[derive(Default)]
+struct FieldOptions {
+    skip: bool,
+    option_flag: Option<Ident>,
+}
+
+fn field_options(field: &Field) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("storage")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                options.skip = true;
+                Ok(())
+            } else if meta.path.is_ident("option_flag") {
+                let name: LitStr = meta.value()?.parse()?;
+                options.option_flag = Some(name.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip` or `option_flag = \"field\"`"))
+            }
+        })?;
+    }
+    if options.skip && options.option_flag.is_some() {
+        return Err(syn::Error::new_spanned(
+            field,
+            "`skip` and `option_flag` can not be used together",
+        ));
+    }
+    Ok(options)
+}
+
+#[derive(Default)]
+struct StructOptions {
+    version: Option<syn::LitInt>,
+    migrate: Option<syn::Path>,
+}
+
+fn struct_options(input: &DeriveInput) -> syn::Result<StructOptions> {
+    let mut options = StructOptions::default();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("storage")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                options.version = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("migrate") {
+                let path: LitStr = meta.value()?.parse()?;
+                options.migrate = Some(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `version = N` or `migrate = \"path\"`"))
+            }
+        })?;
+    }
+    Ok(options)
+}
+
+/// The `T` of a field of type `Option<T>`
+fn option_inner(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Path(p) if p.qself.is_none() => {
+            let segment = p.path.segments.last()?;
+            if segment.ident != "Option" {
+                return None;
+            }
+            match &segment.arguments {
+                PathArguments::AngleBracketed(args) if args.args.len() == 1 => {
+                    match args.args.first()? {
+                        GenericArgument::Type(t) => Some(t),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(s) => &s.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input,
+                "StorageData can only be derived for structs",
+            ))
+        }
+    };
+
+    // local bindings in from_data and member accessors in to_data
+    let members = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match &f.ident {
+            Some(ident) => (format_ident!("__field_{}", ident), quote!(#ident)),
+            None => {
+                let index = Index::from(i);
+                (format_ident!("__field_{}", i), quote!(#index))
+            }
+        })
+        .collect::<Vec<_>>();
+    let binding_of = |flag: &Ident| -> syn::Result<Ident> {
+        fields
+            .iter()
+            .zip(members.iter())
+            .find(|(f, _)| f.ident.as_ref() == Some(flag))
+            .map(|(_, (binding, _))| binding.clone())
+            .ok_or_else(|| syn::Error::new(flag.span(), "unknown option flag field"))
+    };
+
+    let mut reads = vec![];
+    let mut try_reads = vec![];
+    let mut writes = vec![];
+    let mut declared = vec![];
+    for (field, (binding, member)) in fields.iter().zip(members.iter()) {
+        let options = field_options(field)?;
+        let ty = &field.ty;
+        let field_name = member.to_string();
+        if options.skip {
+            reads.push(quote!(let #binding: #ty = ::core::default::Default::default();));
+            try_reads.push(quote!(let #binding: #ty = ::core::default::Default::default();));
+        } else if let Some(flag) = options.option_flag {
+            let inner = option_inner(ty).ok_or_else(|| {
+                syn::Error::new_spanned(ty, "`option_flag` requires an `Option` field")
+            })?;
+            if !declared.contains(&flag) {
+                return Err(syn::Error::new(
+                    flag.span(),
+                    "the option flag must be a field declared before the option",
+                ));
+            }
+            let flag_binding = binding_of(&flag)?;
+            reads.push(quote! {
+                let #binding: #ty = if #flag_binding != 0 {
+                    Some(<#inner as ::zkwasm_rest_abi::StorageData>::from_data(u64data))
+                } else {
+                    None
+                };
+            });
+            try_reads.push(quote! {
+                let #binding: #ty = if #flag_binding != 0 {
+                    Some(
+                        <#inner as ::zkwasm_rest_abi::StorageData>::try_from_data(u64data)
+                            .map_err(|e| e.within(#field_name))?,
+                    )
+                } else {
+                    None
+                };
+            });
+            writes.push(quote! {
+                if self.#flag != 0 {
+                    ::zkwasm_rest_abi::StorageData::to_data(self.#member.as_ref().unwrap(), data);
+                }
+            });
+        } else {
+            reads.push(quote! {
+                let #binding = <#ty as ::zkwasm_rest_abi::StorageData>::from_data(u64data);
+            });
+            try_reads.push(quote! {
+                let #binding = <#ty as ::zkwasm_rest_abi::StorageData>::try_from_data(u64data)
+                    .map_err(|e| e.within(#field_name))?;
+            });
+            writes.push(quote! {
+                ::zkwasm_rest_abi::StorageData::to_data(&self.#member, data);
+            });
+        }
+        if let Some(ident) = &field.ident {
+            declared.push(ident.clone());
+        }
+    }
+
+    let bindings = members.iter().map(|(b, _)| b).collect::<Vec<_>>();
+    let construct = match fields {
+        Fields::Named(_) => {
+            let names = fields.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!(#name { #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(#name ( #(#bindings),* )),
+        Fields::Unit => quote!(#name),
+    };
+
+    let options = struct_options(&input)?;
+    let version = options.version.map(|v| quote!(const VERSION: u64 = #v;));
+    let migrate = options.migrate.map(|path| {
+        quote! {
+            fn migrate(
+                version: u64,
+                u64data: &mut ::core::slice::IterMut<u64>,
+            ) -> ::core::result::Result<Self, ::zkwasm_rest_abi::DecodeError> {
+                #path(version, u64data)
+            }
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::zkwasm_rest_abi::StorageData for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn from_data(u64data: &mut ::core::slice::IterMut<u64>) -> Self {
+                #(#reads)*
+                #construct
+            }
+            #[allow(unused_variables)]
+            fn to_data(&self, data: &mut Vec<u64>) {
+                #(#writes)*
+            }
+            #[allow(unused_variables)]
+            fn try_from_data(
+                u64data: &mut ::core::slice::IterMut<u64>,
+            ) -> ::core::result::Result<Self, ::zkwasm_rest_abi::DecodeError> {
+                #(#try_reads)*
+                Ok(#construct)
+            }
+            #version
+            #migrate
+        }
+    })
+}
+
+#[proc_macro_derive(StorageData, attributes(storage))]
+pub fn derive_storage_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,106 @@
+//! The flow of a bundle, shared by the generated `zkmain` and the replay of the harness.
+//!
+//! `zkmain` reads the number of transactions from the private input and the initial root from
+//! the public input, loads the state, verifies and handles every transaction, finalizes the
+//! bundle and outputs `BundleOutputs` laid out by `ApiOptions::outputs`. `run_bundle` runs the
+//! same steps on any `BundleInput`, so a replay of recorded inputs can not drift from the prover.
+use crate::layout::{BundleCounter, BundleOutputs, EventHasher, PublicOutput};
+use crate::{conclude_settlement, ApiOptions, MERKLE_MAP};
+
+/// The public and private input streams of a bundle
+pub trait BundleInput {
+    fn public(&mut self) -> Result<u64, String>;
+    fn private(&mut self) -> Result<u64, String>;
+}
+
+/// The `wasm_input` streams of the prover
+pub struct WasmInput;
+
+impl BundleInput for WasmInput {
+    fn public(&mut self) -> Result<u64, String> {
+        Ok(unsafe { zkwasm_rust_sdk::wasm_input(1) })
+    }
+    fn private(&mut self) -> Result<u64, String> {
+        Ok(unsafe { zkwasm_rust_sdk::wasm_input(0) })
+    }
+}
+
+/// Entries generated by `create_zkwasm_apis!` that make up a bundle
+pub struct BundleApp {
+    pub options: ApiOptions,
+    pub initialize: fn(Vec<u64>),
+    pub verify_tx_signature: fn(Vec<u64>),
+    pub handle_tx: fn(Vec<u64>) -> Vec<u64>,
+    pub preempt: fn() -> bool,
+    pub finalize: fn() -> Vec<u8>,
+}
+
+pub struct BundleRun {
+    pub outputs: BundleOutputs,
+    /// Whether the state reached its preemption point, `zkmain` fails otherwise
+    pub preempt: bool,
+    /// The settlement returned by `finalize`, committed by `outputs.settlement`
+    pub settlement: Vec<u8>,
+}
+
+/// Run a bundle of `app` on `input`, calling `on_tx` with the index, the command word and the
+/// result of every transaction once it has been handled
+pub fn run_bundle<I: BundleInput>(
+    app: &BundleApp,
+    input: &mut I,
+    verify_signature: bool,
+    mut on_tx: impl FnMut(usize, u64, &[u64]),
+) -> Result<BundleRun, String> {
+    let tx_length = input.private()?;
+    let pre_root = [input.public()?, input.public()?, input.public()?, input.public()?];
+    (app.initialize)(pre_root.to_vec());
+    let bundle_counter = if app.options.has_output(PublicOutput::BundleCounter) {
+        BundleCounter::get()
+    } else {
+        0
+    };
+    let mut events = EventHasher::new();
+
+    let trace = unsafe { zkwasm_rust_sdk::wasm_trace_size() };
+    zkwasm_rust_sdk::dbg!("trace after initialize: {}\n", trace);
+
+    for index in 0..tx_length as usize {
+        let mut params = Vec::with_capacity(20 + 16);
+        for _ in 0..20 {
+            params.push(input.private()?);
+        }
+        let command = input.private()?;
+        let command_length = ((command & 0xff00) >> 8) as usize;
+        if command_length == 0 || command_length >= 16 {
+            return Err(format!("tx {}: invalid command length {}", index, command_length));
+        }
+        params.push(command);
+        for _ in 0..command_length - 1 {
+            params.push(input.private()?);
+        }
+        if verify_signature {
+            (app.verify_tx_signature)(params.clone());
+        }
+        let result = (app.handle_tx)(params);
+        events.update(&result);
+        on_tx(index, command, &result);
+        let trace = unsafe { zkwasm_rust_sdk::wasm_trace_size() };
+        zkwasm_rust_sdk::dbg!("trace track: {}\n", trace);
+    }
+
+    let preempt = (app.preempt)();
+    let settlement = (app.finalize)();
+    let outputs = BundleOutputs {
+        pre_root,
+        root: unsafe { MERKLE_MAP.merkle.root },
+        settlement: conclude_settlement(&app.options, settlement.as_slice()),
+        tx_count: tx_length,
+        event_hash: events.finalize(),
+        bundle_counter,
+    };
+    Ok(BundleRun {
+        outputs,
+        preempt,
+        settlement,
+    })
+}
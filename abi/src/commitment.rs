@@ -0,0 +1,199 @@
+//! Commitment of the settlement of a bundle in the outputs of `zkmain`.
+//!
+//! By default the whole settlement byte string is hashed with SHA-256 (`conclude_tx_info`) and
+//! the settlement contract needs every byte of it. With `SettlementCommitment::Merkle` the
+//! settlement is a list of framed entries (see `frame_settlement_entries`) committed as a binary
+//! SHA-256 merkle tree, so entries can be settled in chunks or claimed one by one with the proofs
+//! of `settlement_merkle_proof`.
+//!
+//! Leaves are `sha256(0x00 | entry)` and nodes `sha256(0x01 | left | right)`. The leaves are
+//! padded with zero hashes up to a power of two and the root of an empty settlement is zero.
+use sha2::{Digest, Sha256};
+
+use crate::conclude_tx_info;
+use crate::layout::{PublicOutput, DEFAULT_OUTPUTS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettlementCommitment {
+    Sha256,
+    Merkle,
+}
+
+/// Encoding of the messages of every bundle in the settlement.
+///
+/// `Legacy` writes the 32 byte records of `WithdrawInfo::flush` and only accepts withdraws of
+/// amounts that fit in 32 bits. `Tagged` writes every message with a leading tag byte, withdraws
+/// as the 57 byte versioned record of `WithdrawInfo::flush_wide`, so it has to be understood by
+/// the settlement contract of the app.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettlementFormat {
+    Legacy,
+    Tagged,
+}
+
+/// Options of `create_zkwasm_apis!`, given after the types as `; key = value, ...`:
+///
+/// ```ignore
+/// create_zkwasm_apis!(Transaction, State, Config, Error; settlement = SettlementCommitment::Merkle);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApiOptions {
+    pub settlement: SettlementCommitment,
+    pub settlement_format: SettlementFormat,
+    /// Outputs of `zkmain` in order, see `layout::public_input_layout`
+    pub outputs: &'static [PublicOutput],
+    /// Check the nonce of every transaction before dispatch, off by default, see `guard`
+    pub replay_guard: bool,
+    /// Chain or app id mixed into the signed message when it is not 0, see `guard`
+    pub domain: u64,
+}
+
+impl ApiOptions {
+    pub const DEFAULT: ApiOptions = ApiOptions {
+        settlement: SettlementCommitment::Sha256,
+        settlement_format: SettlementFormat::Legacy,
+        outputs: DEFAULT_OUTPUTS,
+        replay_guard: false,
+        domain: 0,
+    };
+
+    pub fn has_output(&self, output: PublicOutput) -> bool {
+        self.outputs.contains(&output)
+    }
+}
+
+impl Default for ApiOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Concatenate the entries of a settlement, each preceded by its length as a big endian u32
+pub fn frame_settlement_entries(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(entries.iter().map(|e| e.len() + 4).sum());
+    for entry in entries {
+        bytes.extend_from_slice(&(entry.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(entry);
+    }
+    bytes
+}
+
+/// Split a settlement framed by `frame_settlement_entries`, `None` when it is truncated
+pub fn settlement_entries(mut bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut entries = vec![];
+    while !bytes.is_empty() {
+        let len = u32::from_be_bytes(bytes.get(0..4)?.try_into().unwrap()) as usize;
+        entries.push(bytes.get(4..4 + len)?.to_vec());
+        bytes = &bytes[4 + len..];
+    }
+    Some(entries)
+}
+
+fn hash_leaf(entry: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(entry);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Levels of the tree from the padded leaves to the root
+fn merkle_levels(entries: &[Vec<u8>]) -> Vec<Vec<[u8; 32]>> {
+    let mut level = entries.iter().map(|e| hash_leaf(e)).collect::<Vec<_>>();
+    level.resize(entries.len().next_power_of_two(), [0; 32]);
+    let mut levels = vec![level];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| hash_node(&pair[0], &pair[1]))
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+pub fn settlement_merkle_root(entries: &[Vec<u8>]) -> [u8; 32] {
+    if entries.is_empty() {
+        return [0; 32];
+    }
+    merkle_levels(entries).last().unwrap()[0]
+}
+
+/// Siblings of the entry `index` from the leaf level up
+pub fn settlement_merkle_proof(entries: &[Vec<u8>], index: usize) -> Option<Vec<[u8; 32]>> {
+    if index >= entries.len() {
+        return None;
+    }
+    let levels = merkle_levels(entries);
+    let proof = levels[..levels.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(height, level)| level[(index >> height) ^ 1])
+        .collect();
+    Some(proof)
+}
+
+pub fn verify_settlement_proof(root: &[u8; 32], entry: &[u8], index: usize, proof: &[[u8; 32]]) -> bool {
+    let mut hash = hash_leaf(entry);
+    for (height, sibling) in proof.iter().enumerate() {
+        hash = if (index >> height) & 1 == 0 {
+            hash_node(&hash, sibling)
+        } else {
+            hash_node(sibling, &hash)
+        };
+    }
+    index >> proof.len() == 0 && hash == *root
+}
+
+/// The four words of `zkmain` that commit to the settlement `bytes` returned by `finalize`
+pub fn conclude_settlement(options: &ApiOptions, bytes: &[u8]) -> [u64; 4] {
+    match options.settlement {
+        SettlementCommitment::Sha256 => conclude_tx_info(bytes),
+        SettlementCommitment::Merkle => {
+            let entries = settlement_entries(bytes);
+            crate::enforce(entries.is_some(), "check settlement framing");
+            let root = settlement_merkle_root(&entries.unwrap_or_default());
+            root.chunks_exact(8)
+                .map(|x| u64::from_be_bytes(x.try_into().unwrap()))
+                .collect::<Vec<_>>()
+                .try_into()
+                .unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settlement_proofs() {
+        let entries = (0..5u8).map(|i| vec![i; 3 + i as usize]).collect::<Vec<_>>();
+        let framed = frame_settlement_entries(&entries);
+        assert_eq!(settlement_entries(&framed), Some(entries.clone()));
+        assert_eq!(settlement_entries(&framed[..framed.len() - 1]), None);
+
+        let root = settlement_merkle_root(&entries);
+        for (index, entry) in entries.iter().enumerate() {
+            let proof = settlement_merkle_proof(&entries, index).unwrap();
+            assert_eq!(proof.len(), 3);
+            assert!(verify_settlement_proof(&root, entry, index, &proof));
+            assert!(!verify_settlement_proof(&root, &entries[(index + 1) % 5], index, &proof));
+        }
+        assert_eq!(settlement_merkle_proof(&entries, 5), None);
+        assert_eq!(settlement_merkle_root(&[]), [0; 32]);
+        assert_eq!(
+            conclude_settlement(&ApiOptions::DEFAULT, &framed),
+            conclude_tx_info(&framed)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Replay protection enforced by the ABI.
+//!
+//! Apps opt in with `ApiOptions::replay_guard`, it writes keys of its own and so changes the
+//! merkle roots of an app. The generated `handle_tx` then checks the nonce of every transaction
+//! against a counter of the signer kept by the ABI under its own key, before the transaction is
+//! decoded by the app. The signer and nonce are read as laid out by the convention crate: the pid
+//! of `Player::pkey_to_pid` and the nonce in the bits above 16 of the command word. Once the
+//! transaction has been handled its nonce is consumed, even if the transaction failed: the counter
+//! moves past it, and so does the nonce of the player record when the transaction did not store
+//! the player, so that `Player::check_and_inc_nonce` still finds the nonce of the player equal to
+//! the nonce of the transaction. Signers without a counter yet, e.g. players created before the
+//! guard was enabled, continue from the nonce of their player record.
+//!
+//! With a non zero `ApiOptions::domain` the signed message is the poseidon hash of the domain
+//! followed by the command words instead of the command words alone, so that signatures of one
+//! deployment can not be replayed on another. The options are constants of the app, so the guard
+//! keeps no configuration of its own.
+use zkwasm_rust_sdk::poseidon::PoseidonHasher;
+
+use crate::{enforce, set_data, Player, MERKLE_MAP};
+
+fn to_key(pid: &[u64; 2]) -> [u64; 4] {
+    [pid[0], pid[1], 0xfc00, 0xfc01]
+}
+
+/// The counter of `pid`, only present once the guard has checked a transaction of `pid`
+pub fn recorded_nonce(pid: &[u64; 2]) -> Option<u64> {
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.get(&to_key(pid)).first().copied()
+}
+
+/// The nonce stored in the player record of `pid`, its first word
+fn player_nonce(pid: &[u64; 2]) -> Option<u64> {
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.get(&Player::<u64>::to_key(pid)).first().copied()
+}
+
+/// The nonce expected in the next transaction of `pid`
+pub fn next_nonce(pid: &[u64; 2]) -> u64 {
+    recorded_nonce(pid).or_else(|| player_nonce(pid)).unwrap_or(0)
+}
+
+pub fn check_nonce(pid: &[u64; 2], nonce: u64) {
+    enforce(next_nonce(pid) == nonce, "check tx nonce");
+}
+
+/// Consume `nonce` once its transaction has been handled, whatever the result
+pub fn consume_nonce(pid: &[u64; 2], nonce: u64) {
+    set_data(&to_key(pid), &[nonce + 1]);
+    let key = Player::<u64>::to_key(pid);
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    let mut data = kvpair.get(&key);
+    if data.first() == Some(&nonce) {
+        data[0] = nonce + 1;
+        set_data(&key, &data);
+    }
+}
+
+/// The words hashed into the message signed for `commands`
+pub fn signed_message(domain: u64, commands: &[u64]) -> Vec<u64> {
+    let mut msg = Vec::with_capacity(commands.len() + 1);
+    if domain != 0 {
+        msg.push(domain);
+    }
+    msg.extend_from_slice(commands);
+    msg
+}
+
+/// The poseidon hash signed for `commands` under `domain`
+pub fn message_hash(domain: u64, commands: &[u64]) -> [u64; 4] {
+    PoseidonHasher::hash(&signed_message(domain, commands), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::signed_message;
+
+    #[test]
+    fn test_signed_message() {
+        assert_eq!(signed_message(0, &[1, 2]), vec![1, 2]);
+        assert_eq!(signed_message(7, &[1, 2]), vec![7, 1, 2]);
+    }
+}
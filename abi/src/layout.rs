@@ -0,0 +1,206 @@
+//! Layout of the public inputs and outputs of `zkmain`.
+//!
+//! The public inputs are the four words of the root the bundle starts from. The outputs are
+//! listed by `ApiOptions::outputs` and default to the root after the bundle and the settlement
+//! commitment.
+//! `public_input_layout` describes both so that verifier contracts and prover tooling can be
+//! checked against the app.
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PublicOutput {
+    /// Merkle root before the bundle, the same as the public inputs
+    PreRoot,
+    /// Merkle root after the bundle
+    Root,
+    /// Commitment of the settlement, see `conclude_settlement`
+    Settlement,
+    /// Number of transactions of the bundle
+    TxCount,
+    /// SHA-256 of the results of `handle_tx`, see `EventHasher`
+    EventHash,
+    /// Sequence number of the bundle, see `BundleCounter`
+    BundleCounter,
+}
+
+impl PublicOutput {
+    pub fn words(&self) -> usize {
+        match self {
+            PublicOutput::TxCount | PublicOutput::BundleCounter => 1,
+            _ => 4,
+        }
+    }
+}
+
+/// The two original outputs, apps opt into the others through `ApiOptions::outputs`
+pub const DEFAULT_OUTPUTS: &[PublicOutput] = &[PublicOutput::Root, PublicOutput::Settlement];
+
+/// Reserved key of the bundle counter in the merkle map
+const BUNDLE_COUNTER_KEY: [u64; 4] = [0, 0, 0xfd00, 0xfd01];
+
+/// Number of bundles finalized on top of the state, advanced by the `finalize` generated by
+/// `create_zkwasm_apis!` when `PublicOutput::BundleCounter` is one of the outputs. Its value when
+/// a bundle starts is the sequence number of the bundle, so consecutive bundles output
+/// consecutive numbers and the pre root of a bundle is the root of the previous one. Apps that do
+/// not output the counter never store it, so their state roots are not changed by it.
+pub struct BundleCounter;
+
+impl BundleCounter {
+    pub fn get() -> u64 {
+        let kvpair = unsafe { &mut crate::MERKLE_MAP };
+        kvpair.get(&BUNDLE_COUNTER_KEY).first().copied().unwrap_or(0)
+    }
+
+    /// Increase the counter and return its previous value
+    pub fn advance() -> u64 {
+        let counter = Self::get();
+        crate::set_data(&BUNDLE_COUNTER_KEY, &[counter + 1]);
+        counter
+    }
+}
+
+/// Hash of the results of the transactions of a bundle: every result is hashed as its length
+/// followed by its words, all as little endian u64
+pub struct EventHasher(Sha256);
+
+impl EventHasher {
+    pub fn new() -> Self {
+        EventHasher(Sha256::new())
+    }
+
+    pub fn update(&mut self, result: &[u64]) {
+        self.0.update((result.len() as u64).to_le_bytes());
+        for word in result {
+            self.0.update(word.to_le_bytes());
+        }
+    }
+
+    pub fn finalize(self) -> [u64; 4] {
+        self.0
+            .finalize()
+            .chunks_exact(8)
+            .map(|x| u64::from_be_bytes(x.try_into().unwrap()))
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+}
+
+impl Default for EventHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Values of a bundle that can be output by `zkmain`
+pub struct BundleOutputs {
+    pub pre_root: [u64; 4],
+    pub root: [u64; 4],
+    pub settlement: [u64; 4],
+    pub tx_count: u64,
+    pub event_hash: [u64; 4],
+    pub bundle_counter: u64,
+}
+
+impl BundleOutputs {
+    pub fn words(&self, outputs: &[PublicOutput]) -> Vec<u64> {
+        let mut words = vec![];
+        for output in outputs {
+            match output {
+                PublicOutput::PreRoot => words.extend_from_slice(&self.pre_root),
+                PublicOutput::Root => words.extend_from_slice(&self.root),
+                PublicOutput::Settlement => words.extend_from_slice(&self.settlement),
+                PublicOutput::TxCount => words.push(self.tx_count),
+                PublicOutput::EventHash => words.extend_from_slice(&self.event_hash),
+                PublicOutput::BundleCounter => words.push(self.bundle_counter),
+            }
+        }
+        words
+    }
+}
+
+#[derive(Serialize)]
+struct Field {
+    name: PublicOutput,
+    offset: usize,
+    words: usize,
+}
+
+#[derive(Serialize)]
+struct Layout {
+    inputs: Vec<Field>,
+    outputs: Vec<Field>,
+    settlement: &'static str,
+    settlement_format: &'static str,
+}
+
+fn fields(items: &[PublicOutput]) -> Vec<Field> {
+    let mut offset = 0;
+    items
+        .iter()
+        .map(|name| {
+            let field = Field {
+                name: *name,
+                offset,
+                words: name.words(),
+            };
+            offset += field.words;
+            field
+        })
+        .collect()
+}
+
+/// Json description of the public inputs and outputs of `zkmain` with `options`, e.g.
+/// `{"inputs":[{"name":"pre_root","offset":0,"words":4}],"outputs":[...],"settlement":"sha256",
+/// "settlement_format":"legacy"}`
+pub fn public_input_layout(options: &crate::ApiOptions) -> String {
+    let layout = Layout {
+        inputs: fields(&[PublicOutput::PreRoot]),
+        outputs: fields(options.outputs),
+        settlement: match options.settlement {
+            crate::SettlementCommitment::Sha256 => "sha256",
+            crate::SettlementCommitment::Merkle => "merkle",
+        },
+        settlement_format: match options.settlement_format {
+            crate::SettlementFormat::Legacy => "legacy",
+            crate::SettlementFormat::Tagged => "tagged",
+        },
+    };
+    serde_json::to_string(&layout).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ApiOptions;
+
+    #[test]
+    fn test_output_layout() {
+        let outputs = BundleOutputs {
+            pre_root: [1; 4],
+            root: [2; 4],
+            settlement: [3; 4],
+            tx_count: 5,
+            event_hash: [6; 4],
+            bundle_counter: 7,
+        };
+        assert_eq!(outputs.words(DEFAULT_OUTPUTS), vec![2, 2, 2, 2, 3, 3, 3, 3]);
+        assert!(!ApiOptions::DEFAULT.has_output(PublicOutput::BundleCounter));
+        assert_eq!(
+            outputs.words(&[PublicOutput::TxCount, PublicOutput::PreRoot]),
+            vec![5, 1, 1, 1, 1]
+        );
+
+        let options = ApiOptions {
+            outputs: &[PublicOutput::Root, PublicOutput::TxCount, PublicOutput::EventHash],
+            ..ApiOptions::DEFAULT
+        };
+        let layout: serde_json::Value = serde_json::from_str(&public_input_layout(&options)).unwrap();
+        assert_eq!(layout["outputs"][1], serde_json::json!({"name": "tx_count", "offset": 4, "words": 1}));
+        assert_eq!(layout["outputs"][2]["offset"], 5);
+        assert_eq!(layout["settlement"], "sha256");
+        assert_eq!(layout["settlement_format"], "legacy");
+    }
+}
@@ -2,7 +2,6 @@ use sha2::{Sha256, Digest};
 use zkwasm_rust_sdk::jubjub::BabyJubjubPoint;
 use zkwasm_rust_sdk::jubjub::JubjubSignature;
 use zkwasm_rust_sdk::kvpair::KeyValueMap;
-use zkwasm_rust_sdk::poseidon::PoseidonHasher;
 use zkwasm_rust_sdk::Merkle;
 use serde::Serialize;
 use primitive_types::U256;
@@ -19,9 +18,339 @@ pub static mut MERKLE_MAP: KeyValueMap<Merkle> = KeyValueMap {
         ]}
 };
 
+/// A key of the merkle map written by a transaction with the value it holds afterwards
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StateChange {
+    pub key: [u64; 4],
+    pub data: Vec<u64>,
+}
+
+/// The writes of the current transaction, each key once in the order of its first write
+#[derive(Default)]
+struct Changes {
+    index: std::collections::HashMap<[u64; 4], usize>,
+    writes: Vec<StateChange>,
+}
+
+thread_local! {
+    static CHANGES: std::cell::RefCell<Changes> = std::cell::RefCell::new(Changes::default());
+}
+
+/// Write `data` under `key` in `MERKLE_MAP` and record it in the changes of the current
+/// transaction, see `TxResult::changes`. Writes to `MERKLE_MAP` that bypass it are not reported.
+pub fn set_data(key: &[u64; 4], data: &[u64]) {
+    CHANGES.with(|changes| {
+        let changes = &mut *changes.borrow_mut();
+        match changes.index.get(key) {
+            Some(i) => changes.writes[*i].data = data.to_vec(),
+            None => {
+                changes.index.insert(*key, changes.writes.len());
+                changes.writes.push(StateChange {
+                    key: *key,
+                    data: data.to_vec(),
+                });
+            }
+        }
+    });
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.set(key, data);
+}
+
+/// Take the writes of `set_data` since the last call, in the order of the first write of each key
+/// and with the last value written
+pub fn take_changes() -> Vec<StateChange> {
+    CHANGES.with(|changes| changes.take().writes)
+}
+
+// allows the StorageData derive to refer to this crate from inside it
+extern crate self as zkwasm_rest_abi;
+
+pub use zkwasm_rest_abi_derive::StorageData;
+
+pub mod bundle;
+pub mod commitment;
+pub mod guard;
+pub mod layout;
+pub mod settlement;
+pub use commitment::{conclude_settlement, ApiOptions, SettlementCommitment, SettlementFormat};
+pub use layout::PublicOutput;
+
+/// Failure to decode a `StorageData` from its words.
+///
+/// `field` is the path of the field that could not be decoded (e.g. `data.bid.bidprice`) and
+/// `offset` is the index of the word where decoding failed, the length of the record when it is
+/// truncated. While the error propagates through `try_from_data`, `offset` holds the number of
+/// words left after the failure and is made absolute by `try_decode`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub field: String,
+    pub offset: usize,
+}
+
+impl DecodeError {
+    pub fn new(field: &str, remaining: usize) -> Self {
+        DecodeError {
+            field: field.to_string(),
+            offset: remaining,
+        }
+    }
+
+    /// Prefix the field path with the name of the enclosing field
+    pub fn within(mut self, name: &str) -> Self {
+        self.field = if self.field.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", name, self.field)
+        };
+        self
+    }
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "can not decode field `{}` at word {}", self.field, self.offset)
+    }
+}
+
+/// Read the next word of a record, failing with an error for `field` when the record is truncated
+pub fn next_word(u64data: &mut IterMut<u64>, field: &str) -> Result<u64, DecodeError> {
+    u64data
+        .next()
+        .map(|x| *x)
+        .ok_or_else(|| DecodeError::new(field, 0))
+}
+
+/// Read the next word of a record as a 0/1 flag
+fn next_flag(u64data: &mut IterMut<u64>, field: &str) -> Result<bool, DecodeError> {
+    match next_word(u64data, field)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(DecodeError::new(field, u64data.len() + 1)),
+    }
+}
+
 pub trait StorageData {
     fn from_data(u64data: &mut IterMut<u64>) -> Self;
     fn to_data(&self, u64data: &mut Vec<u64>);
+
+    /// Decode without panicking on malformed data. The default implementation can not detect
+    /// errors and calls `from_data`, hand-written implementations should override it.
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        Ok(Self::from_data(u64data))
+    }
+
+    /// Decode a whole record, see `decode_record` for the words left after it
+    fn try_decode(data: &mut [u64]) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        decode_record(data, Self::VERSION != 0, |u64data| Self::try_from_data(u64data))
+    }
+
+    /// Version of the layout of top level records (players and the global state). The version of
+    /// a record of a type with a non zero version is stored under its own key, see `version_key`,
+    /// records stored before the type was versioned have none and are version 0.
+    const VERSION: u64 = 0;
+
+    /// Upgrade a record stored with an older layout `version`
+    fn migrate(version: u64, u64data: &mut IterMut<u64>) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        let _ = version;
+        Err(DecodeError::new("<version>", u64data.len()))
+    }
+
+    /// Decode a record stored with layout `version`, migrating it when it is older
+    fn try_from_version(version: u64, u64data: &mut IterMut<u64>) -> Result<Self, DecodeError>
+    where
+        Self: Sized,
+    {
+        if version == Self::VERSION {
+            Self::try_from_data(u64data)
+        } else if version < Self::VERSION {
+            Self::migrate(version, u64data)
+        } else {
+            Err(DecodeError::new("<version>", u64data.len()))
+        }
+    }
+}
+
+/// Tags the key of the version of a record, see `version_key`
+const VERSION_KEY_TAG: u64 = 0x7a77 << 48;
+
+/// Key of the layout version of the record stored under `key`. The version is kept out of the
+/// record, so that no word of a record stored before its type was versioned can be mistaken for
+/// a version.
+pub fn version_key(key: &[u64; 4]) -> [u64; 4] {
+    [key[0], key[1], key[2] ^ VERSION_KEY_TAG, key[3] ^ VERSION_KEY_TAG]
+}
+
+/// The layout version of the record of `T` stored under `key`, 0 when none is stored. Types with
+/// version 0 never store one and skip the lookup.
+pub fn load_version<T: StorageData>(key: &[u64; 4]) -> u64 {
+    if T::VERSION == 0 {
+        return 0;
+    }
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.get(&version_key(key)).first().copied().unwrap_or(0)
+}
+
+/// Record that the record under `key` is stored with the layout of `T`, only written when the
+/// stored version differs
+pub fn store_version<T: StorageData>(key: &[u64; 4]) {
+    if T::VERSION != 0 && load_version::<T>(key) != T::VERSION {
+        set_data(&version_key(key), &[T::VERSION]);
+    }
+}
+
+/// Decode a whole record of `T` stored with layout `version`, migrating older layouts
+pub fn decode_versioned<T: StorageData>(version: u64, data: &mut [u64]) -> Result<T, DecodeError> {
+    decode_record(data, T::VERSION != 0, |u64data| T::try_from_version(version, u64data))
+}
+
+/// Decode a whole record with `f`, turning the offset of errors into word indexes. With `strict`
+/// it fails when `f` leaves words unread, otherwise they are ignored: records of unversioned types
+/// stored before `decode_record` existed may carry trailing words, while the layout of versioned
+/// types is exact.
+pub fn decode_record<R>(
+    data: &mut [u64],
+    strict: bool,
+    f: impl FnOnce(&mut IterMut<u64>) -> Result<R, DecodeError>,
+) -> Result<R, DecodeError> {
+    let total = data.len();
+    let mut u64data = data.iter_mut();
+    let r = f(&mut u64data).map_err(|mut e| {
+        e.offset = total - e.offset;
+        e
+    })?;
+    if strict && u64data.len() != 0 {
+        return Err(DecodeError {
+            field: "<end>".to_string(),
+            offset: total - u64data.len(),
+        });
+    }
+    Ok(r)
+}
+
+impl StorageData for u64 {
+    fn from_data(u64data: &mut IterMut<u64>) -> Self {
+        *u64data.next().unwrap()
+    }
+    fn to_data(&self, data: &mut Vec<u64>) {
+        data.push(*self);
+    }
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        next_word(u64data, "")
+    }
+}
+
+impl StorageData for u32 {
+    fn from_data(u64data: &mut IterMut<u64>) -> Self {
+        *u64data.next().unwrap() as u32
+    }
+    fn to_data(&self, data: &mut Vec<u64>) {
+        data.push(*self as u64);
+    }
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        let v = next_word(u64data, "")?;
+        u32::try_from(v).map_err(|_| DecodeError::new("", u64data.len() + 1))
+    }
+}
+
+impl StorageData for bool {
+    fn from_data(u64data: &mut IterMut<u64>) -> Self {
+        *u64data.next().unwrap() != 0
+    }
+    fn to_data(&self, data: &mut Vec<u64>) {
+        data.push(*self as u64);
+    }
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        next_flag(u64data, "")
+    }
+}
+
+impl<T: StorageData, const N: usize> StorageData for [T; N] {
+    fn from_data(u64data: &mut IterMut<u64>) -> Self {
+        std::array::from_fn(|_| T::from_data(u64data))
+    }
+    fn to_data(&self, data: &mut Vec<u64>) {
+        for t in self.iter() {
+            t.to_data(data);
+        }
+    }
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        let mut items = Vec::with_capacity(N);
+        for i in 0..N {
+            items.push(T::try_from_data(u64data).map_err(|e| e.within(&format!("[{}]", i)))?);
+        }
+        Ok(items.try_into().ok().unwrap())
+    }
+}
+
+/// Stored as the number of elements followed by the elements
+impl<T: StorageData> StorageData for Vec<T> {
+    fn from_data(u64data: &mut IterMut<u64>) -> Self {
+        let len = *u64data.next().unwrap() as usize;
+        (0..len).map(|_| T::from_data(u64data)).collect()
+    }
+    fn to_data(&self, data: &mut Vec<u64>) {
+        data.push(self.len() as u64);
+        for t in self.iter() {
+            t.to_data(data);
+        }
+    }
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        let len = next_word(u64data, "len")? as usize;
+        if len > u64data.len() {
+            return Err(DecodeError::new("len", u64data.len() + 1));
+        }
+        let mut items = Vec::with_capacity(len);
+        for i in 0..len {
+            items.push(T::try_from_data(u64data).map_err(|e| e.within(&format!("[{}]", i)))?);
+        }
+        Ok(items)
+    }
+}
+
+/// Stored as a presence word (0 or 1) followed by the value when present
+impl<T: StorageData> StorageData for Option<T> {
+    fn from_data(u64data: &mut IterMut<u64>) -> Self {
+        if *u64data.next().unwrap() != 0 {
+            Some(T::from_data(u64data))
+        } else {
+            None
+        }
+    }
+    fn to_data(&self, data: &mut Vec<u64>) {
+        match self {
+            None => data.push(0),
+            Some(t) => {
+                data.push(1);
+                t.to_data(data);
+            }
+        }
+    }
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        if next_flag(u64data, "")? {
+            Ok(Some(T::try_from_data(u64data)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T> StorageData for std::marker::PhantomData<T> {
+    fn from_data(_u64data: &mut IterMut<u64>) -> Self {
+        std::marker::PhantomData
+    }
+    fn to_data(&self, _data: &mut Vec<u64>) {}
+    fn try_from_data(_u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        Ok(std::marker::PhantomData)
+    }
 }
 
 pub struct WithdrawInfo { // 32bits in total
@@ -42,13 +371,41 @@ impl WithdrawInfo {
             amount: limbs[0] & 0xffffffff
         }
     }
+    /// Withdraw of a full 64 bit `amount`, the address is packed in `limbs` as for `new` and the
+    /// low 32 bits of `limbs[0]` are ignored
+    pub fn new_wide(limbs: &[u64; 3], amount: u64, token_index: u32) -> Self {
+        WithdrawInfo {
+            amount,
+            ..Self::new(limbs, token_index)
+        }
+    }
+
+    /// Whether the amount fits in the 32 bit amount of the legacy record
+    pub fn is_legacy(&self) -> bool {
+        self.amount <= u32::MAX as u64
+    }
+
+    /// Legacy 32 byte record: feature (4) address (20) amount (8), amounts above 32 bits are not
+    /// understood by legacy settlement contracts, see `flush_wide` and `SettlementFormat`
     pub fn flush(&self, bytes: &mut Vec<u8>) {
         bytes.extend_from_slice(&self.feature.to_le_bytes());
         bytes.extend_from_slice(&self.address);
         bytes.extend_from_slice(&self.amount.to_be_bytes()); //solidity needs be endian
     }
+
+    /// Versioned 57 byte record: version (1) feature (4) address (20) amount (32 as uint256)
+    pub fn flush_wide(&self, bytes: &mut Vec<u8>) {
+        bytes.push(WITHDRAW_RECORD_VERSION);
+        bytes.extend_from_slice(&self.feature.to_le_bytes());
+        bytes.extend_from_slice(&self.address);
+        bytes.extend_from_slice(&[0u8; 24]);
+        bytes.extend_from_slice(&self.amount.to_be_bytes()); //solidity needs be endian
+    }
 }
 
+/// Version byte leading every record written by `WithdrawInfo::flush_wide`
+pub const WITHDRAW_RECORD_VERSION: u8 = 0x01;
+
 
 #[derive(Debug, Serialize)]
 pub struct Player<T: StorageData + Default> {
@@ -68,58 +425,62 @@ impl<T: StorageData + Default> Player<T> {
     }
 
     pub fn store(&self) {
+        let key = Self::to_key(&self.player_id);
         let mut data = Vec::new();
         data.push(self.nonce);
         self.data.to_data(&mut data);
-        let kvpair = unsafe { &mut MERKLE_MAP };
-        kvpair.set(&Self::to_key(&self.player_id), data.as_slice());
+        set_data(&key, data.as_slice());
+        store_version::<T>(&key);
     }
 
+    /// A new player starts at nonce 0, or at the nonce consumed so far by the replay guard
     pub fn new_from_pid(pid: [u64; 2]) -> Self {
         Self {
             player_id: pid,
-            nonce: 0,
+            nonce: guard::recorded_nonce(&pid).unwrap_or(0),
             data: T::default(),
         }
     }
 
     pub fn get_from_pid(pid: &[u64; 2]) -> Option<Self> {
+        Self::try_get_from_pid(pid).unwrap_or_else(|e| {
+            enforce(false, &format!("decode player {:?}: {}", pid, e));
+            unreachable!()
+        })
+    }
+
+    /// Like `get_from_pid` but returns an error when the stored player does not match the layout
+    /// of `T` and can not be migrated, e.g. when it was stored by an older version of the app
+    pub fn try_get_from_pid(pid: &[u64; 2]) -> Result<Option<Self>, DecodeError> {
         let key = Self::to_key(pid);
         let kvpair = unsafe { &mut MERKLE_MAP };
         let mut data = kvpair.get(&key);
         if data.is_empty() {
-            None
+            Ok(None)
         } else {
-            let mut dataslice = data.iter_mut();
-            let p = Player {
-                player_id: pid.clone(),
-                nonce: *dataslice.next().unwrap(),
-                data: T::from_data(&mut dataslice),
-            };
-            Some(p)
+            let version = load_version::<T>(&key);
+            decode_record(&mut data, T::VERSION != 0, |u64data| {
+                Ok(Some(Player {
+                    player_id: pid.clone(),
+                    nonce: next_word(u64data, "nonce")?,
+                    data: T::try_from_version(version, u64data).map_err(|e| e.within("data"))?,
+                }))
+            })
         }
     }
 
     // Non-store of get and check and inc nonce
     pub fn get_and_check_nonce(pid: &[u64; 2], nonce: u64) -> Self {
-        let player_opt = Self::get_from_pid(pid);
-        match player_opt {
-            None => {
-                unsafe {zkwasm_rust_sdk::require(nonce == 0)};
-                let mut player = Self::new_from_pid(pid.clone());
-                player.nonce = 1;
-                player
-            },
-            Some (mut player) => {
-                player.check_and_inc_nonce(nonce);
-                player
-            }
-        }
+        let mut player = Self::get_from_pid(pid).unwrap_or_else(|| Self::new_from_pid(*pid));
+        player.check_and_inc_nonce(nonce);
+        player
     }
 
+    /// The replay guard of the ABI keeps the nonce of the player in step with its own counter,
+    /// so the check holds with or without it
     pub fn check_and_inc_nonce(&mut self, nonce: u64) {
         unsafe {zkwasm_rust_sdk::require(self.nonce == nonce)};
-        self.nonce += 1;
+        self.nonce = nonce + 1;
     }
 
 }
@@ -142,8 +503,9 @@ pub fn enforce(x: bool, hint: &str) {
     }
 }
 
-#[wasm_bindgen]
-pub fn verify_tx_signature(inputs: Vec<u64>) {
+/// Verify the signature of a transaction over its command words signed under `domain`, the
+/// generated `verify_tx_signature` passes the domain of the api options
+pub fn verify_signature(domain: u64, inputs: &[u64]) {
     let pk = BabyJubjubPoint {
         x: U256([
                 inputs[0],
@@ -181,8 +543,7 @@ pub fn verify_tx_signature(inputs: Vec<u64>) {
             inputs[19],
         ]
     };
-    let commands = &inputs[20..];
-    let msg = PoseidonHasher::hash(commands, true);
+    let msg = guard::message_hash(domain, &inputs[20..]);
     sig.verify(&pk, &msg);
 }
 
@@ -199,16 +560,224 @@ pub fn conclude_tx_info(data: &[u8]) -> [u64;4] {
     result.try_into().unwrap()
 }
 
+/// Error codes of an application, see `tx_errors!`
+pub trait TxError: Sized + Copy {
+    fn code(&self) -> u32;
+    fn from_code(code: u32) -> Option<Self>;
+    fn description(&self) -> &'static str;
+
+    fn decode_error(code: u32) -> &'static str {
+        Self::from_code(code).map_or("Unknown", |e| e.description())
+    }
+}
+
+/// Declare the error enum of an application. The variant names are used as the descriptions
+/// returned by `decode_error`, 0 is reserved for success.
+///
+/// ```ignore
+/// tx_errors! {
+///     pub enum Error {
+///         PlayerAlreadyExist = 1,
+///         PlayerNotExist = 2,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! tx_errors {
+    ($vis: vis enum $E: ident { $($V: ident = $code: expr),* $(,)? }) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(u32)]
+        $vis enum $E {
+            $($V = $code),*
+        }
+
+        impl $crate::TxError for $E {
+            fn code(&self) -> u32 {
+                *self as u32
+            }
+            fn from_code(code: u32) -> Option<Self> {
+                match code {
+                    $(c if c == $E::$V as u32 => Some($E::$V),)*
+                    _ => None,
+                }
+            }
+            fn description(&self) -> &'static str {
+                match self {
+                    $($E::$V => stringify!($V),)*
+                }
+            }
+        }
+
+        impl From<$E> for u32 {
+            fn from(e: $E) -> u32 {
+                e as u32
+            }
+        }
+    };
+}
+
+/// Result of a transaction as returned by `handle_tx`.
+///
+/// The result is serialized as `[code, n, events[0..n], root[0..4], m, changes]`: `code` is 0 on
+/// success or the error code of the app, followed by the length and words of the events emitted by
+/// the transaction, the merkle root after the transaction and the `m` entries of its state delta.
+/// Each entry is `[key[0..4], len, data[0..len]]`, a key of the merkle map written by the
+/// transaction and the value it holds afterwards.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TxResult {
+    pub code: u32,
+    pub events: Vec<u64>,
+    pub root: [u64; 4],
+    /// Writes of `set_data`, filled by `encode_tx_result`
+    pub changes: Vec<StateChange>,
+}
+
+impl TxResult {
+    pub fn success(events: Vec<u64>) -> Self {
+        TxResult {
+            code: 0,
+            events,
+            root: unsafe { MERKLE_MAP.merkle.root },
+            changes: vec![],
+        }
+    }
+
+    pub fn error(code: u32) -> Self {
+        TxResult {
+            code,
+            events: vec![],
+            root: unsafe { MERKLE_MAP.merkle.root },
+            changes: vec![],
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+
+    pub fn to_vec(&self) -> Vec<u64> {
+        let mut data = Vec::with_capacity(self.events.len() + self.changes.len() * 5 + 7);
+        data.push(self.code as u64);
+        data.push(self.events.len() as u64);
+        data.extend_from_slice(&self.events);
+        data.extend_from_slice(&self.root);
+        data.push(self.changes.len() as u64);
+        for change in self.changes.iter() {
+            data.extend_from_slice(&change.key);
+            data.push(change.data.len() as u64);
+            data.extend_from_slice(&change.data);
+        }
+        data
+    }
+
+    pub fn from_slice(data: &[u64]) -> Option<Self> {
+        let n = *data.get(1)? as usize;
+        let m = *data.get(n.checked_add(6)?)? as usize;
+        let mut rest = &data[n + 7..];
+        let mut changes = Vec::with_capacity(m.min(rest.len() / 5));
+        for _ in 0..m {
+            let len = *rest.get(4)? as usize;
+            let end = len.checked_add(5)?;
+            if rest.len() < end {
+                return None;
+            }
+            changes.push(StateChange {
+                key: rest[..4].try_into().unwrap(),
+                data: rest[5..end].to_vec(),
+            });
+            rest = &rest[end..];
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(TxResult {
+            code: data[0] as u32,
+            events: data[2..2 + n].to_vec(),
+            root: data[2 + n..6 + n].try_into().unwrap(),
+            changes,
+        })
+    }
+}
+
+/// Legacy results of `process`: the error code followed by the events
+impl From<Vec<u64>> for TxResult {
+    fn from(data: Vec<u64>) -> Self {
+        let mut result = TxResult::error(data.first().map_or(0, |c| *c as u32));
+        result.events = data.into_iter().skip(1).collect();
+        result
+    }
+}
+
+impl<E: TxError> From<Result<Vec<u64>, E>> for TxResult {
+    fn from(r: Result<Vec<u64>, E>) -> Self {
+        match r {
+            Ok(events) => TxResult::success(events),
+            Err(e) => TxResult::error(e.code()),
+        }
+    }
+}
+
+pub fn encode_tx_result<R: Into<TxResult>>(r: R) -> Vec<u64> {
+    let mut result = r.into();
+    result.changes = take_changes();
+    result.to_vec()
+}
+
+/// Generate the wasm entries of an application.
+///
+/// With `($T, $S, $C)` the output of `$T::process` is returned by `handle_tx` as is and errors
+/// are described by `$T::decode_error`. With `($T, $S, $C, $E)` the output of `process` is
+/// converted into a `TxResult` and errors are described by the `TxError` enum `$E`.
+///
+/// Either form can be followed by the fields of `ApiOptions` as `; key = value, ...`, the options
+/// are exported as `API_OPTIONS` and the layout of the outputs of `zkmain` is described by the
+/// generated `public_input_layout`.
 #[macro_export]
 macro_rules! create_zkwasm_apis {
-    ($T: ident, $S: ident, $C: ident) => {
+    ($T: ident, $S: ident, $C: ident $(; $($key: ident = $val: expr),+ $(,)?)?) => {
+        $crate::create_zkwasm_apis!(
+            @impl $T, $S, $C,
+            std::convert::identity,
+            $T::decode_error,
+            $crate::ApiOptions { $($($key: $val,)+)? ..$crate::ApiOptions::DEFAULT }
+        );
+    };
+    ($T: ident, $S: ident, $C: ident, $E: ident $(; $($key: ident = $val: expr),+ $(,)?)?) => {
+        $crate::create_zkwasm_apis!(
+            @impl $T, $S, $C,
+            $crate::encode_tx_result,
+            <$E as $crate::TxError>::decode_error,
+            $crate::ApiOptions { $($($key: $val,)+)? ..$crate::ApiOptions::DEFAULT }
+        );
+    };
+    (@impl $T: ident, $S: ident, $C: ident, $encode: expr, $decode: expr, $options: expr) => {
+        pub const API_OPTIONS: $crate::ApiOptions = $options;
+
         #[wasm_bindgen]
         pub fn handle_tx(params: Vec<u64>) -> Vec<u64> {
             let user_address = [params[0], params[1], params[2], params[3]];
             let sig_r = [params[16], params[17], params[18], params[19]];
             let command = &params[20..];
+            // only report the writes of this transaction in its result
+            $crate::take_changes();
+            // the pid of the signer as in `Player::pkey_to_pid`, the nonce as in the command word
+            let signer = [params[1], params[2]];
+            let nonce = command[0] >> 16;
+            if API_OPTIONS.replay_guard {
+                $crate::guard::check_nonce(&signer, nonce);
+            }
             let transaction = $T::decode(command);
-            transaction.process(&user_address, &sig_r)
+            let result = transaction.process(&user_address, &sig_r);
+            if API_OPTIONS.replay_guard {
+                $crate::guard::consume_nonce(&signer, nonce);
+            }
+            ($encode)(result)
+        }
+
+        /// Verify the signature of a transaction under the domain of `API_OPTIONS`
+        #[wasm_bindgen]
+        pub fn verify_tx_signature(params: Vec<u64>) {
+            $crate::verify_signature(API_OPTIONS.domain, &params);
         }
 
         #[wasm_bindgen]
@@ -223,7 +792,7 @@ macro_rules! create_zkwasm_apis {
 
         #[wasm_bindgen]
         pub fn decode_error(e: u32) -> String {
-            $T::decode_error(e).to_string()
+            ($decode)(e).to_string()
         }
 
 
@@ -232,6 +801,16 @@ macro_rules! create_zkwasm_apis {
             $C::to_json_string()
         }
 
+        #[wasm_bindgen]
+        pub fn signature_domain() -> u64 {
+            API_OPTIONS.domain
+        }
+
+        #[wasm_bindgen]
+        pub fn public_input_layout() -> String {
+            $crate::layout::public_input_layout(&API_OPTIONS)
+        }
+
         #[wasm_bindgen]
         pub fn preempt() -> bool{
             $S::preempt()
@@ -257,67 +836,51 @@ macro_rules! create_zkwasm_apis {
             };
         }
 
+        /// Reset the global state of the app before loading another root, e.g. between the tests
+        /// of the harness
+        pub fn reset() {
+            $S::reset();
+        }
+
         #[wasm_bindgen]
         pub fn finalize() -> Vec<u8> {
             unsafe {
-                let bytes = $S::flush_settlement();
+                let bytes = $crate::settlement::SettlementOutput::encode($S::flush_settlement(), &API_OPTIONS);
+                if API_OPTIONS.has_output($crate::PublicOutput::BundleCounter) {
+                    $crate::layout::BundleCounter::advance();
+                }
                 $S::store();
                 bytes
             }
         }
 
+        /// Run a bundle on `input` as `zkmain` does, see `bundle::run_bundle`
+        pub fn run_bundle<I: $crate::bundle::BundleInput>(
+            input: &mut I,
+            verify_signature: bool,
+            on_tx: impl FnMut(usize, u64, &[u64]),
+        ) -> Result<$crate::bundle::BundleRun, String> {
+            let app = $crate::bundle::BundleApp {
+                options: API_OPTIONS,
+                initialize,
+                verify_tx_signature,
+                handle_tx,
+                preempt,
+                finalize,
+            };
+            $crate::bundle::run_bundle(&app, input, verify_signature, on_tx)
+        }
+
         #[wasm_bindgen]
         pub fn zkmain() {
-            use zkwasm_rust_sdk::wasm_input;
-            use zkwasm_rust_sdk::wasm_output;
-            use zkwasm_rust_sdk::wasm_trace_size;
-            let merkle_ref = unsafe {&mut MERKLE_MAP};
-            let tx_length = unsafe {wasm_input(0)};
-
-            unsafe {
-                initialize([wasm_input(1), wasm_input(1), wasm_input(1), wasm_input(1)].to_vec())
-            }
-
-            let trace = unsafe {wasm_trace_size()};
-            zkwasm_rust_sdk::dbg!("trace after initialize: {}\n", trace);
-
-
-            for _ in 0..tx_length {
-                let mut params = Vec::with_capacity(20 + 16);
-                for _ in 0..20 {
-                    params.push(unsafe {wasm_input(0)});
-                }
-                let command = unsafe {wasm_input(0)};
-                let command_length = ((command & 0xff00) >> 8)  as usize;
-                enforce(command_length < 16, "check command length");
-                params.push(command);
-                for _  in 0..command_length - 1 {
-                    params.push(unsafe {wasm_input(0)});
-                }
-                verify_tx_signature(params.clone());
-                handle_tx(params);
-                let trace = unsafe {wasm_trace_size()};
-                zkwasm_rust_sdk::dbg!("trace track: {}\n", trace);
-            }
-
-            enforce(preempt(), "check preempt");
-
-            let bytes = finalize();
-            let txdata = conclude_tx_info(bytes.as_slice());
-
-            let root = merkle_ref.merkle.root;
-            unsafe {
-                wasm_output(root[0]);
-                wasm_output(root[1]);
-                wasm_output(root[2]);
-                wasm_output(root[3]);
-            }
-
-            unsafe {
-                wasm_output(txdata[0]);
-                wasm_output(txdata[1]);
-                wasm_output(txdata[2]);
-                wasm_output(txdata[3]);
+            let run = run_bundle(&mut $crate::bundle::WasmInput, true, |_, _, _| ())
+                .unwrap_or_else(|e| {
+                    $crate::enforce(false, &e);
+                    unreachable!()
+                });
+            $crate::enforce(run.preempt, "check preempt");
+            for word in run.outputs.words(API_OPTIONS.outputs) {
+                unsafe { zkwasm_rust_sdk::wasm_output(word) };
             }
         }
     }
@@ -332,3 +895,183 @@ pub fn test_merkle() {
     let data = kvpair.get(&[0,0,0,0]);
     unsafe {zkwasm_rust_sdk::require(data == [123])};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeError, StateChange, StorageData, TxResult, WithdrawInfo, WITHDRAW_RECORD_VERSION};
+
+    #[derive(Debug, Default, PartialEq, StorageData)]
+    struct Inner {
+        a: u64,
+        b: [u64; 2],
+    }
+
+    #[derive(Debug, PartialEq, StorageData)]
+    struct Outer {
+        flag: u64,
+        #[storage(option_flag = "flag")]
+        inner: Option<Inner>,
+        small: u32,
+        enabled: bool,
+        list: Vec<Inner>,
+        maybe: Option<u64>,
+        #[storage(skip)]
+        cache: u64,
+    }
+
+    #[test]
+    fn test_derive_storage_data() {
+        let outer = Outer {
+            flag: 1,
+            inner: Some(Inner { a: 2, b: [3, 4] }),
+            small: 5,
+            enabled: true,
+            list: vec![Inner { a: 6, b: [7, 8] }],
+            maybe: None,
+            cache: 9,
+        };
+        let mut data = vec![];
+        outer.to_data(&mut data);
+        assert_eq!(data, vec![1, 2, 3, 4, 5, 1, 1, 6, 7, 8, 0]);
+        let decoded = Outer::from_data(&mut data.iter_mut());
+        assert_eq!(decoded, Outer { cache: 0, ..outer });
+
+        let empty = Outer {
+            flag: 0,
+            inner: None,
+            small: 0,
+            enabled: false,
+            list: vec![],
+            maybe: Some(3),
+            cache: 0,
+        };
+        let mut data = vec![];
+        empty.to_data(&mut data);
+        assert_eq!(data, vec![0, 0, 0, 0, 1, 3]);
+        assert_eq!(Outer::from_data(&mut data.iter_mut()), empty);
+        assert_eq!(Outer::try_decode(&mut data), Ok(empty));
+    }
+
+    #[derive(Debug, PartialEq, StorageData)]
+    struct Legacy {
+        a: u64,
+    }
+
+    /// Version 1 adds `b` after `a`
+    #[derive(Debug, PartialEq)]
+    struct Upgraded {
+        a: u64,
+        b: u64,
+    }
+
+    impl StorageData for Upgraded {
+        fn from_data(u64data: &mut core::slice::IterMut<u64>) -> Self {
+            Upgraded {
+                a: *u64data.next().unwrap(),
+                b: *u64data.next().unwrap(),
+            }
+        }
+        fn to_data(&self, data: &mut Vec<u64>) {
+            data.push(self.a);
+            data.push(self.b);
+        }
+        const VERSION: u64 = 1;
+        fn migrate(
+            version: u64,
+            u64data: &mut core::slice::IterMut<u64>,
+        ) -> Result<Self, DecodeError> {
+            assert_eq!(version, 0);
+            Ok(Upgraded {
+                a: super::next_word(u64data, "a")?,
+                b: 0,
+            })
+        }
+    }
+
+    #[test]
+    fn test_versioned_records() {
+        let mut data = vec![];
+        Legacy { a: 3 }.to_data(&mut data);
+        assert_eq!(data, vec![3]);
+        assert_eq!(super::decode_versioned::<Upgraded>(0, &mut data), Ok(Upgraded { a: 3, b: 0 }));
+
+        // the record holds the same words whatever its version
+        let mut data = vec![];
+        Upgraded { a: 3, b: 4 }.to_data(&mut data);
+        assert_eq!(data, vec![3, 4]);
+        assert_eq!(super::decode_versioned::<Upgraded>(1, &mut data), Ok(Upgraded { a: 3, b: 4 }));
+
+        // records of a newer layout are rejected
+        let e = super::decode_versioned::<Upgraded>(2, &mut data).unwrap_err();
+        assert_eq!(e.field, "<version>");
+
+        let key = [5, 6, 0xff00, 0xff01];
+        assert_ne!(super::version_key(&key), key);
+        assert_eq!(super::version_key(&super::version_key(&key)), key);
+    }
+
+    #[test]
+    fn test_decode_error() {
+        // truncated inside the optional inner struct
+        let mut data = vec![1, 2, 3];
+        let e = Outer::try_decode(&mut data).unwrap_err();
+        assert_eq!(e, DecodeError { field: "inner.b.[1]".to_string(), offset: 3 });
+
+        // invalid bool
+        let mut data = vec![0, 5, 2, 0, 0];
+        let e = Outer::try_decode(&mut data).unwrap_err();
+        assert_eq!(e, DecodeError { field: "enabled".to_string(), offset: 2 });
+
+        // words left after the record are ignored for unversioned types only
+        let mut data = vec![0, 5, 1, 0, 0, 7];
+        assert!(Outer::try_decode(&mut data).is_ok());
+        let mut data = vec![3, 4, 7];
+        let e = Upgraded::try_decode(&mut data).unwrap_err();
+        assert_eq!(e, DecodeError { field: "<end>".to_string(), offset: 2 });
+    }
+
+    #[test]
+    fn test_tx_result_layout() {
+        let result = TxResult {
+            code: 3,
+            events: vec![7, 8],
+            root: [1, 2, 3, 4],
+            changes: vec![
+                StateChange { key: [5, 6, 0xff00, 0xff01], data: vec![9, 10] },
+                StateChange { key: [0, 0, 0, 0], data: vec![] },
+            ],
+        };
+        let data = result.to_vec();
+        // the root keeps its offset after the events
+        assert_eq!(data[..8], [3, 2, 7, 8, 1, 2, 3, 4]);
+        assert_eq!(data[8], 2);
+        assert_eq!(data[9..16], [5, 6, 0xff00, 0xff01, 2, 9, 10]);
+        assert_eq!(data.len(), 21);
+        assert_eq!(TxResult::from_slice(&data), Some(result));
+        assert_eq!(TxResult::from_slice(&data[..20]), None);
+        assert_eq!(TxResult::from_slice(&data[..8]), None);
+    }
+
+    #[test]
+    fn test_withdraw_records() {
+        let limbs = [(0x11223344 << 32) | 5, 0x0102030405060708, 0x090a0b0c0d0e0f10];
+        let legacy = WithdrawInfo::new(&limbs, 2);
+        assert_eq!(legacy.amount, 5);
+        assert!(legacy.is_legacy());
+        let mut bytes = vec![];
+        legacy.flush(&mut bytes);
+        assert_eq!(bytes.len(), 32);
+
+        let wide = WithdrawInfo::new_wide(&limbs, 1 << 40, 2);
+        assert_eq!(wide.address, legacy.address);
+        assert!(!wide.is_legacy());
+        let mut bytes = vec![];
+        wide.flush_wide(&mut bytes);
+        assert_eq!(bytes.len(), 57);
+        assert_eq!(bytes[0], WITHDRAW_RECORD_VERSION);
+        assert_eq!(&bytes[1..5], &2u32.to_le_bytes());
+        assert_eq!(&bytes[5..25], &legacy.address);
+        assert_eq!(&bytes[25..49], &[0u8; 24]);
+        assert_eq!(&bytes[49..], &(1u64 << 40).to_be_bytes());
+    }
+}
@@ -0,0 +1,206 @@
+//! Messages of a bundle to the settlement contract.
+//!
+//! The generated `finalize` encodes the messages returned by the `flush_settlement` of the app
+//! in the `SettlementFormat` of its `ApiOptions` and frames them for `SettlementCommitment::Merkle`,
+//! so the layout of the settlement only depends on the options of the app.
+use primitive_types::U256;
+
+use crate::commitment::frame_settlement_entries;
+use crate::{enforce, ApiOptions, SettlementCommitment, SettlementFormat};
+use crate::{WithdrawInfo, WITHDRAW_RECORD_VERSION};
+
+/* Tags of the outbound messages in the tagged settlement encoding, a withdraw is the versioned
+ * record of `WithdrawInfo::flush_wide` whose version byte doubles as its tag
+ */
+pub const TAG_WITHDRAW: u8 = WITHDRAW_RECORD_VERSION;
+pub const TAG_NFT_TRANSFER: u8 = 0x02;
+pub const TAG_CALL: u8 = 0x03;
+
+/// Transfer of the token `token_id` of the NFT contract `contract` to `to`, token ids are uint256
+/// as in ERC-721
+#[derive(Clone, Debug, PartialEq)]
+pub struct NftTransferInfo {
+    pub contract: [u8; 20],
+    pub token_id: U256,
+    pub to: [u8; 20],
+}
+
+/// Call of the contract `target` with an arbitrary `payload` interpreted by the contract
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallInfo {
+    pub target: [u8; 20],
+    pub payload: Vec<u8>,
+}
+
+/// A message of the bundle to the settlement contract
+pub enum SettlementMessage {
+    Withdraw(WithdrawInfo),
+    NftTransfer(NftTransferInfo),
+    Call(CallInfo),
+}
+
+impl SettlementMessage {
+    /// Append the message in `format`, `false` when `format` can not hold it, i.e. when a legacy
+    /// settlement holds anything but a withdraw of an amount that fits in 32 bits
+    pub fn flush(&self, format: SettlementFormat, bytes: &mut Vec<u8>) -> bool {
+        match (format, self) {
+            (SettlementFormat::Tagged, _) => {
+                self.flush_tagged(bytes);
+                true
+            }
+            (SettlementFormat::Legacy, SettlementMessage::Withdraw(info)) if info.is_legacy() => {
+                info.flush(bytes);
+                true
+            }
+            (SettlementFormat::Legacy, _) => false,
+        }
+    }
+
+    /// Encoding:
+    /// withdraw: 0x01 | feature (4) | address (20) | amount (32)
+    /// nft transfer: 0x02 | contract (20) | token id (32) | to (20)
+    /// call: 0x03 | target (20) | payload length (4) | payload
+    /// with the integers above one byte in big endian but the little endian feature
+    pub fn flush_tagged(&self, bytes: &mut Vec<u8>) {
+        match self {
+            SettlementMessage::Withdraw(info) => info.flush_wide(bytes),
+            SettlementMessage::NftTransfer(info) => {
+                bytes.push(TAG_NFT_TRANSFER);
+                bytes.extend_from_slice(&info.contract);
+                let mut token_id = [0u8; 32];
+                info.token_id.to_big_endian(&mut token_id);
+                bytes.extend_from_slice(&token_id);
+                bytes.extend_from_slice(&info.to);
+            }
+            SettlementMessage::Call(info) => {
+                bytes.push(TAG_CALL);
+                bytes.extend_from_slice(&info.target);
+                bytes.extend_from_slice(&(info.payload.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(&info.payload);
+            }
+        }
+    }
+}
+
+/// Encode `messages` in the settlement format of `options`, framed by `frame_settlement_entries`
+/// with `SettlementCommitment::Merkle`
+pub fn encode_settlement(options: &ApiOptions, messages: &[SettlementMessage]) -> Vec<u8> {
+    let entries = messages
+        .iter()
+        .map(|s| {
+            let mut bytes = vec![];
+            enforce(s.flush(options.settlement_format, &mut bytes), "check settlement format");
+            bytes
+        })
+        .collect::<Vec<_>>();
+    match options.settlement {
+        SettlementCommitment::Sha256 => entries.concat(),
+        SettlementCommitment::Merkle => frame_settlement_entries(&entries),
+    }
+}
+
+/// What the `flush_settlement` of an app returns to the generated `finalize`
+pub trait SettlementOutput {
+    fn encode(self, options: &ApiOptions) -> Vec<u8>;
+}
+
+/// Messages encoded with `encode_settlement`
+impl SettlementOutput for Vec<SettlementMessage> {
+    fn encode(self, options: &ApiOptions) -> Vec<u8> {
+        encode_settlement(options, &self)
+    }
+}
+
+/// Settlement bytes encoded by the app itself, which has to frame them for
+/// `SettlementCommitment::Merkle`
+impl SettlementOutput for Vec<u8> {
+    fn encode(self, _options: &ApiOptions) -> Vec<u8> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApiOptions, SettlementCommitment};
+
+    #[test]
+    fn test_withdraw_formats() {
+        let limbs = [0x11223344 << 32, 0x0102030405060708, 0x090a0b0c0d0e0f10];
+        let small = SettlementMessage::Withdraw(WithdrawInfo::new_wide(&limbs, 5, 0));
+        let wide = SettlementMessage::Withdraw(WithdrawInfo::new_wide(&limbs, 1 << 40, 0));
+
+        let mut bytes = vec![];
+        assert!(small.flush(SettlementFormat::Legacy, &mut bytes));
+        assert_eq!(bytes.len(), 32);
+        assert!(!wide.flush(SettlementFormat::Legacy, &mut vec![]));
+
+        // the tagged format does not depend on the amount
+        for message in [small, wide] {
+            let mut bytes = vec![];
+            assert!(message.flush(SettlementFormat::Tagged, &mut bytes));
+            assert_eq!(bytes.len(), 57);
+        }
+    }
+
+    #[test]
+    fn test_tagged_messages() {
+        let mut bytes = vec![];
+        SettlementMessage::NftTransfer(NftTransferInfo {
+            contract: [1; 20],
+            token_id: U256([7, 0, 0, 1 << 56]),
+            to: [2; 20],
+        })
+        .flush_tagged(&mut bytes);
+        assert_eq!(bytes.len(), 73);
+        assert_eq!(bytes[0], TAG_NFT_TRANSFER);
+        assert_eq!(&bytes[21..53], &{
+            let mut id = [0u8; 32];
+            id[0] = 1;
+            id[31] = 7;
+            id
+        });
+        assert_eq!(&bytes[53..], &[2; 20]);
+
+        let mut bytes = vec![];
+        SettlementMessage::Call(CallInfo {
+            target: [3; 20],
+            payload: vec![9, 8, 7],
+        })
+        .flush_tagged(&mut bytes);
+        assert_eq!(bytes[0], TAG_CALL);
+        assert_eq!(&bytes[21..25], &[0, 0, 0, 3]);
+        assert_eq!(&bytes[25..], &[9, 8, 7]);
+
+        // the messages are only understood by contracts that opted into the tagged format
+        let message = SettlementMessage::Call(CallInfo {
+            target: [3; 20],
+            payload: vec![],
+        });
+        assert!(!message.flush(SettlementFormat::Legacy, &mut vec![]));
+        assert!(message.flush(SettlementFormat::Tagged, &mut vec![]));
+    }
+
+    #[test]
+    fn test_encode_settlement() {
+        let limbs = [0, 0, 0];
+        let messages = || {
+            vec![
+                SettlementMessage::Withdraw(WithdrawInfo::new_wide(&limbs, 5, 0)),
+                SettlementMessage::Withdraw(WithdrawInfo::new_wide(&limbs, 6, 0)),
+            ]
+        };
+        assert_eq!(encode_settlement(&ApiOptions::DEFAULT, &messages()).len(), 64);
+        let options = ApiOptions {
+            settlement: SettlementCommitment::Merkle,
+            settlement_format: SettlementFormat::Tagged,
+            ..ApiOptions::DEFAULT
+        };
+        let bytes = messages().encode(&options);
+        let entries = crate::commitment::settlement_entries(&bytes).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.len() == 57));
+        // raw bytes of apps that encode their own settlement are kept as is
+        assert_eq!(vec![1u8, 2, 3].encode(&options), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,88 @@
+use serde::Serialize;
+use zkwasm_rest_abi::{enforce, set_data, Player, StorageData, MERKLE_MAP};
+
+use crate::player::{CommandHandler, WithBalance, ERROR_LAST_ADMIN, ERROR_NOT_ADMIN, ERROR_PLAYER_NOT_EXIST};
+
+/// Reserved key of the admin set in the merkle map
+const ADMIN_KEY: [u64; 4] = [0, 0, 0xfe00, 0xfe01];
+
+/// Player ids allowed to run privileged commands such as `Deposit`
+///
+/// The set is stored in the merkle map and seeded with `CommonState::genesis_admins` the first
+/// time the state is initialized. An empty set rejects every privileged command.
+#[derive(Clone, Debug, Default, Serialize, StorageData)]
+pub struct AdminSet(pub Vec<[u64; 2]>);
+
+impl AdminSet {
+    pub fn load() -> Self {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let mut data = kvpair.get(&ADMIN_KEY);
+        if data.is_empty() {
+            AdminSet::default()
+        } else {
+            AdminSet::try_decode(&mut data).unwrap_or_else(|e| {
+                enforce(false, &format!("decode admin set: {}", e));
+                unreachable!()
+            })
+        }
+    }
+
+    pub fn store(&self) {
+        let mut data = vec![];
+        self.to_data(&mut data);
+        set_data(&ADMIN_KEY, data.as_slice());
+    }
+
+    /// Store `admins` unless an admin set has already been stored
+    pub fn seed(admins: Vec<[u64; 2]>) {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        if kvpair.get(&ADMIN_KEY).is_empty() && !admins.is_empty() {
+            AdminSet(admins).store();
+        }
+    }
+
+    pub fn contains(&self, pid: &[u64; 2]) -> bool {
+        self.0.contains(pid)
+    }
+
+    /// Role check of privileged commands, apps can use it for their own privileged activities
+    pub fn check_admin(pid: &[u64; 2]) -> Result<(), u32> {
+        if Self::load().contains(pid) {
+            Ok(())
+        } else {
+            Err(ERROR_NOT_ADMIN)
+        }
+    }
+}
+
+/// Add or remove an admin, only admins can rotate the admin set and the last admin can not be
+/// removed
+#[derive(Clone)]
+pub struct RotateAdmin {
+    pub add: bool,
+    pub pid: [u64; 2],
+}
+
+impl CommandHandler for RotateAdmin {
+    fn handle<P: StorageData + WithBalance + Default>(&self, pid: &[u64; 2], nonce: u64, _rand: &[u64; 4], _counter: u64) -> Result<(), u32> {
+        let mut admins = AdminSet::load();
+        if !admins.contains(pid) {
+            return Err(ERROR_NOT_ADMIN);
+        }
+        let mut admin = Player::<P>::get_from_pid(pid).ok_or(ERROR_PLAYER_NOT_EXIST)?;
+        admin.check_and_inc_nonce(nonce);
+        if self.add {
+            if !admins.contains(&self.pid) {
+                admins.0.push(self.pid);
+            }
+        } else {
+            admins.0.retain(|x| *x != self.pid);
+            if admins.0.is_empty() {
+                return Err(ERROR_LAST_ADMIN);
+            }
+        }
+        admins.store();
+        admin.store();
+        Ok(())
+    }
+}
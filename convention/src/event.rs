@@ -1,6 +1,6 @@
 use core::slice::IterMut;
 use std::collections::LinkedList;
-use zkwasm_rest_abi::{StorageData, MERKLE_MAP};
+use zkwasm_rest_abi::{next_word, set_data, DecodeError, StorageData, MERKLE_MAP};
 
 /// There are two different events convention in ZKWASM app, one is the scheduled event that can be
 /// tracked in the global state and triggerred by the ticker.
@@ -128,6 +128,12 @@ impl<T: EventHandler + Sized> StorageData for EventQueue<T> {
         let list = LinkedList::new();
         EventQueue { counter, list }
     }
+
+    fn try_from_data(u64data: &mut IterMut<u64>) -> Result<Self, DecodeError> {
+        let counter = next_word(u64data, "counter")?;
+        let list = LinkedList::new();
+        Ok(EventQueue { counter, list })
+    }
 }
 
 
@@ -137,8 +143,7 @@ impl<T: EventHandler + Sized> EventQueue<T> {
         kvpair.get(&[counter & 0xeffffff, EVENTS_LEAF_INDEX, 0, EVENTS_LEAF_INDEX])
     }
     fn set_entries(&self, entries: &Vec<u64>, counter: u64) {
-        let kvpair = unsafe { &mut MERKLE_MAP };
-        kvpair.set(
+        set_data(
             &[counter & 0xeffffff, EVENTS_LEAF_INDEX, 0, EVENTS_LEAF_INDEX],
             entries.as_slice(),
         );
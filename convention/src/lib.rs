@@ -1,16 +1,21 @@
 #![feature(linked_list_cursors)]
 use serde::Serialize;
 use std::cell::{Ref, RefMut};
-use zkwasm_rest_abi::{Player, StorageData, MERKLE_MAP};
+use zkwasm_rest_abi::{
+    decode_versioned, enforce, load_version, set_data, store_version, DecodeError, Player,
+    StorageData, MERKLE_MAP,
+};
 
 pub mod player;
 pub mod settlement;
 pub mod event;
 pub mod objects;
+pub mod admin;
 
 use settlement::*;
+use admin::AdminSet;
 
-pub trait CommonState: Serialize + StorageData + Sized {
+pub trait CommonState: Serialize + StorageData + Default + Sized {
     type PlayerData: StorageData + Default + Serialize;
 
     fn get_global<'a>() -> Ref<'a, Self>;
@@ -36,20 +41,43 @@ pub trait CommonState: Serialize + StorageData + Sized {
         return false;
     }
 
+    /// Player ids of the admins seeded into an empty `AdminSet`
+    fn genesis_admins() -> Vec<[u64; 2]> {
+        vec![]
+    }
+
     fn store(&self) {
         let mut data = vec![];
         self.to_data(&mut data);
-        let kvpair = unsafe { &mut MERKLE_MAP };
-        kvpair.set(&[0, 0, 0, 0], data.as_slice());
+        set_data(&[0, 0, 0, 0], data.as_slice());
+        store_version::<Self>(&[0, 0, 0, 0]);
+    }
+
+    /// Drop the global state and the settlement messages and events of the bundle, so that the
+    /// next `initialize` starts from the merkle map alone, as a fresh instance of the app does
+    fn reset() {
+        *Self::get_global_mut() = Self::default();
+        SettlementInfo::clear();
+        event::clear_events(vec![]);
     }
 
     fn initialize() {
+        if let Err(e) = Self::try_initialize() {
+            enforce(false, &format!("decode global state: {}", e));
+        }
+    }
+
+    /// Load the global state, migrating older layouts, and fail when the stored state can not be
+    /// decoded
+    fn try_initialize() -> Result<(), DecodeError> {
         let kvpair = unsafe { &mut MERKLE_MAP };
         let mut data = kvpair.get(&[0, 0, 0, 0]);
         if !data.is_empty() {
-            let mut u64data = data.iter_mut();
-            *Self::get_global_mut() = Self::from_data(&mut u64data);
+            let version = load_version::<Self>(&[0, 0, 0, 0]);
+            *Self::get_global_mut() = decode_versioned::<Self>(version, &mut data)?;
         }
+        AdminSet::seed(Self::genesis_admins());
+        Ok(())
     }
 }
 
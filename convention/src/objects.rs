@@ -1,8 +1,7 @@
 use std::marker::PhantomData;
-use core::slice::IterMut;
 use serde::Serialize;
 
-use zkwasm_rest_abi::{Player, StorageData, MERKLE_MAP};
+use zkwasm_rest_abi::{enforce, set_data, DecodeError, Player, StorageData, MERKLE_MAP};
 
 use crate::player::WithBalance;
 use crate::event::insert_event;
@@ -16,8 +15,7 @@ impl<P: StorageData> Wrapped<P> {
     pub fn store(&self) {
         let mut data = Vec::new();
         self.data.to_data(&mut data);
-        let kvpair = unsafe { &mut MERKLE_MAP };
-        kvpair.set(&self.key, data.as_slice());
+        set_data(&self.key, data.as_slice());
     }
 }
 
@@ -34,17 +32,25 @@ pub trait IndexedObject<P: StorageData> {
     }
 
     fn get_object(index: u64) -> Option<Wrapped<P>> {
+        Self::try_get_object(index).unwrap_or_else(|e| {
+            enforce(false, &format!("decode object {}: {}", index, e));
+            unreachable!()
+        })
+    }
+
+    /// Like `get_object` but returns an error when the stored object does not match the layout
+    /// of `P`
+    fn try_get_object(index: u64) -> Result<Option<Wrapped<P>>, DecodeError> {
         let kvpair = unsafe { &mut MERKLE_MAP };
         let key = [Self::PREFIX + (index << 16), Self::POSTFIX, Self::POSTFIX, Self::POSTFIX];
         let mut data = kvpair.get(&key);
         if data.is_empty() {
-            None
+            Ok(None)
         } else {
-            let mut dataslice = data.iter_mut();
-            Some(Wrapped {
+            Ok(Some(Wrapped {
                 key,
-                data: P::from_data(&mut dataslice),
-            })
+                data: P::try_decode(&mut data)?,
+            }))
         }
     }
     fn emit_event(index: u64, p: &P) {
@@ -105,59 +111,20 @@ pub trait Position<P: StorageData> {
     }
 }
 
-#[derive(Clone, Serialize, Default, Copy)]
+#[derive(Clone, Serialize, Default, Copy, StorageData)]
 pub struct MarketInfo<Object: StorageData, PlayerData: StorageData + Default + WithBalance> {
     pub marketid: u64, 
     pub askprice: u64,
     pub settleinfo: u64,
+    #[storage(option_flag = "settleinfo")]
     pub bid: Option<BidInfo>,
     pub owner: [u64; 2],
     pub object: Object,
+    #[storage(skip)]
     pub user: PhantomData<PlayerData>,
 }
 
-impl<O: StorageData, Player: StorageData + Default + WithBalance> StorageData for MarketInfo<O, Player> {
-    fn from_data(u64data: &mut IterMut<u64>) -> MarketInfo<O, Player> {
-        let marketid= *u64data.next().unwrap();
-        let askprice = *u64data.next().unwrap();
-        let settleinfo = *u64data.next().unwrap();
-        let mut bidder = None;
-        if settleinfo != 0 {
-            let bidprice = *u64data.next().unwrap();
-            bidder = Some(BidInfo {
-                bidprice,
-                bidder: [*u64data.next().unwrap(), *u64data.next().unwrap()]
-            })
-        }
-        let owner = [*u64data.next().unwrap(), *u64data.next().unwrap()];
-        let object = O::from_data(u64data);
-        MarketInfo {
-            marketid,
-            askprice,
-            settleinfo,
-            bid: bidder,
-            owner,
-            object,
-            user: PhantomData
-        }
-    }
-    fn to_data(&self, data: &mut Vec<u64>) {
-        data.push(self.marketid);
-        data.push(self.askprice);
-        data.push(self.settleinfo);
-        if self.settleinfo != 0 {
-            let bid = self.bid.unwrap();
-            data.push(bid.bidprice);
-            data.push(bid.bidder[0]);
-            data.push(bid.bidder[1]);
-        }
-        data.push(self.owner[0]);
-        data.push(self.owner[1]);
-        self.object.to_data(data)
-    }
-}
-
-#[derive(Clone, Serialize, Default, Copy)]
+#[derive(Clone, Serialize, Default, Copy, StorageData)]
 pub struct BidInfo {
     pub bidprice: u64,
     pub bidder: [u64; 2]
@@ -241,3 +208,63 @@ pub trait BidObject<PlayerData: StorageData + Default + WithBalance> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{BidInfo, MarketInfo};
+    use crate::player::WithBalance;
+    use std::marker::PhantomData;
+    use zkwasm_rest_abi::StorageData;
+
+    #[derive(Default)]
+    struct Balance(u64);
+
+    impl StorageData for Balance {
+        fn from_data(u64data: &mut core::slice::IterMut<u64>) -> Self {
+            Balance(*u64data.next().unwrap())
+        }
+        fn to_data(&self, data: &mut Vec<u64>) {
+            data.push(self.0);
+        }
+    }
+
+    impl WithBalance for Balance {
+        fn cost_balance(&mut self, amount: u64) -> Result<(), u32> {
+            self.0 -= amount;
+            Ok(())
+        }
+        fn inc_balance(&mut self, amount: u64) {
+            self.0 += amount;
+        }
+    }
+
+    #[test]
+    fn test_market_info_layout() {
+        // marketid, askprice, settleinfo, [bidprice, bidder] when settleinfo != 0, owner, object
+        let market = MarketInfo::<u64, Balance> {
+            marketid: 1,
+            askprice: 2,
+            settleinfo: 1,
+            bid: Some(BidInfo {
+                bidprice: 3,
+                bidder: [4, 5],
+            }),
+            owner: [6, 7],
+            object: 8,
+            user: PhantomData,
+        };
+        let mut data = vec![];
+        market.to_data(&mut data);
+        assert_eq!(data, vec![1, 2, 1, 3, 4, 5, 6, 7, 8]);
+        let decoded = MarketInfo::<u64, Balance>::from_data(&mut data.iter_mut());
+        assert_eq!(decoded.get_bidder().unwrap().bidder, [4, 5]);
+        assert_eq!(decoded.object, 8);
+
+        let mut data = vec![1, 2, 0, 6, 7, 8];
+        let unbid = MarketInfo::<u64, Balance>::from_data(&mut data.iter_mut());
+        assert!(unbid.bid.is_none());
+        let mut encoded = vec![];
+        unbid.to_data(&mut encoded);
+        assert_eq!(encoded, vec![1, 2, 0, 6, 7, 8]);
+    }
+}
@@ -1,10 +1,62 @@
+use serde::Serialize;
 use zkwasm_rest_abi::{Player, StorageData, enforce};
-use zkwasm_rest_abi::WithdrawInfo;
+use zkwasm_rest_abi::{ApiOptions, SettlementFormat, WithdrawInfo};
 use crate::SettlementInfo;
+use crate::admin::{AdminSet, RotateAdmin};
 
 pub trait WithBalance {
     fn cost_balance(&mut self, amount: u64) -> Result<(), u32>;
     fn inc_balance(&mut self, amount: u64);
+
+    /// Cost the balance of the token `token`, players that only hold token 0 can keep the default
+    fn cost_token_balance(&mut self, token: u32, amount: u64) -> Result<(), u32> {
+        if token == 0 {
+            self.cost_balance(amount)
+        } else {
+            Err(ERROR_UNSUPPORTED_TOKEN)
+        }
+    }
+
+    fn inc_token_balance(&mut self, token: u32, amount: u64) -> Result<(), u32> {
+        if token == 0 {
+            self.inc_balance(amount);
+            Ok(())
+        } else {
+            Err(ERROR_UNSUPPORTED_TOKEN)
+        }
+    }
+}
+
+/// Balances of several tokens indexed by token index
+#[derive(Clone, Debug, Default, Serialize, StorageData)]
+pub struct TokenBalances(pub Vec<u64>);
+
+impl TokenBalances {
+    pub fn get(&self, token: u32) -> u64 {
+        self.0.get(token as usize).cloned().unwrap_or(0)
+    }
+
+    pub fn cost(&mut self, token: u32, amount: u64) -> Result<(), u32> {
+        match self.0.get_mut(token as usize) {
+            Some(balance) if *balance >= amount => {
+                *balance -= amount;
+                Ok(())
+            }
+            _ if amount == 0 => Ok(()),
+            _ => Err(ERROR_INSUFFICIENT_BALANCE),
+        }
+    }
+
+    pub fn inc(&mut self, token: u32, amount: u64) -> Result<(), u32> {
+        if token >= MAX_TOKENS {
+            return Err(ERROR_UNSUPPORTED_TOKEN);
+        }
+        if self.0.len() <= token as usize {
+            self.0.resize(token as usize + 1, 0);
+        }
+        self.0[token as usize] += amount;
+        Ok(())
+    }
 }
 
 pub trait SubCommand: Sized {
@@ -22,6 +74,8 @@ pub enum Command<Activity: SubCommand> {
     // standard withdraw and deposit
     Withdraw(Withdraw),
     Deposit(Deposit),
+    // admin set rotation
+    RotateAdmin(RotateAdmin),
     // standard player install and timer
     InstallPlayer,
     Tick,
@@ -37,18 +91,38 @@ pub struct TransactionData<Activity: SubCommand> {
  * 2 for Withdraw
  * 3 for Deposit
  * 4 customize commands
+ * 0xfd for Withdraw of a token
+ * 0xfe for RotateAdmin
  */
 const TICK: u64 = 0;
 const INSTALL_PLAYER: u64 = 1;
 const WITHDRAW: u64 = 2;
 const DEPOSIT: u64 = 3;
 pub const COMMAND_BASE:u64 = 4;
+const WITHDRAW_TOKEN: u64 = 0xfd;
+const ROTATE_ADMIN: u64 = 0xfe;
+
+/// Command length of a withdraw with a 64 bit amount in `params[5]`
+const WIDE_WITHDRAW_LENGTH: u64 = 6;
 
 pub const ERROR_PLAYER_ALREADY_EXIST: u32 = 1;
 pub const ERROR_PLAYER_NOT_EXIST: u32 = 2;
 
+/// Codes from `ERROR_BASE` are reserved for the errors added to the convention since, apps number
+/// their own errors below it
+pub const ERROR_BASE: u32 = 0xff00;
+pub const ERROR_INSUFFICIENT_BALANCE: u32 = ERROR_BASE + 1;
+pub const ERROR_UNSUPPORTED_TOKEN: u32 = ERROR_BASE + 2;
+pub const ERROR_NOT_ADMIN: u32 = ERROR_BASE + 3;
+pub const ERROR_LAST_ADMIN: u32 = ERROR_BASE + 4;
+
+/// Largest number of tokens held in `TokenBalances`
+pub const MAX_TOKENS: u32 = 64;
+
 #[derive (Clone)]
 pub struct Withdraw {
+    pub token: u32,
+    pub amount: u64,
     pub data: [u64; 3],
 }
 
@@ -59,10 +133,8 @@ impl CommandHandler for Withdraw {
             None => Err(ERROR_PLAYER_NOT_EXIST),
             Some(player) => {
                 player.check_and_inc_nonce(nonce);
-                let amount = self.data[0] & 0xffffffff;
-                player.data.cost_balance(amount)?;
-                let withdrawinfo =
-                    WithdrawInfo::new(&[self.data[0], self.data[1], self.data[2]], 0);
+                player.data.cost_token_balance(self.token, self.amount)?;
+                let withdrawinfo = WithdrawInfo::new_wide(&self.data, self.amount, self.token);
                 SettlementInfo::append_settlement(withdrawinfo);
                 player.store();
                 Ok(())
@@ -73,18 +145,20 @@ impl CommandHandler for Withdraw {
 
 #[derive (Clone)]
 pub struct Deposit {
+    pub token: u32,
     pub data: [u64; 3],
 }
 
 impl CommandHandler for Deposit {
     fn handle<P: StorageData + WithBalance + Default>(&self, pid: &[u64; 2], nonce: u64, _rand: &[u64; 4], _counter: u64) -> Result<(), u32> {
-        let mut admin = Player::<P>::get_from_pid(pid).unwrap();
+        AdminSet::check_admin(pid)?;
+        let mut admin = Player::<P>::get_from_pid(pid).ok_or(ERROR_PLAYER_NOT_EXIST)?;
         admin.check_and_inc_nonce(nonce);
         let mut player = Player::<P>::get_from_pid(&[self.data[0], self.data[1]]);
         match player.as_mut() {
             None => Err(ERROR_PLAYER_NOT_EXIST),
             Some(player) => {
-                player.data.inc_balance(self.data[2]);
+                player.data.inc_token_balance(self.token, self.data[2])?;
                 player.store();
                 admin.store();
                 Ok(())
@@ -94,18 +168,50 @@ impl CommandHandler for Deposit {
 }
 
 impl<Activity: SubCommand> TransactionData<Activity> {
+    /// Decode with the default options, i.e. without withdraws of 64 bit amounts
     pub fn decode(params: &[u64]) -> Self {
+        Self::decode_with(params, &ApiOptions::DEFAULT)
+    }
+
+    /// Decode the commands accepted by an app with `options`, withdraws of 64 bit amounts need the
+    /// tagged settlement format
+    pub fn decode_with(params: &[u64], options: &ApiOptions) -> Self {
         let command = params[0] & 0xff;
         let nonce = params[0] >> 16;
-        let command = if command == WITHDRAW {
+        let command = if command == WITHDRAW || command == WITHDRAW_TOKEN {
+            // the legacy withdraw always settles token 0 and ignores `params[1]`, the withdraw of
+            // a token reads the token index there
+            let token = if command == WITHDRAW_TOKEN {
+                enforce(params[1] <= u32::MAX as u64, "check withdraw token index");
+                params[1] as u32
+            } else {
+                0
+            };
+            // a withdraw of length 6 carries a full 64 bit amount after the address limbs
+            let amount = if (params[0] >> 8) & 0xff == WIDE_WITHDRAW_LENGTH {
+                enforce(options.settlement_format == SettlementFormat::Tagged, "check wide withdraw format");
+                enforce(params[2] & 0xffffffff == 0, "check wide withdraw address");
+                params[5]
+            } else {
+                params[2] & 0xffffffff
+            };
             Command::Withdraw (Withdraw {
+                token,
+                amount,
                 data: [params[2], params[3], params[4]]
             })
         } else if command == DEPOSIT {
-            enforce(params[3] == 0, "check deposit index"); // only token index 0 is supported
+            enforce(params[3] <= u32::MAX as u64, "check deposit token index");
             Command::Deposit (Deposit {
+                token: params[3] as u32,
                 data: [params[1], params[2], params[4]]
             })
+        } else if command == ROTATE_ADMIN {
+            enforce(params[1] <= 1, "check rotate admin operation");
+            Command::RotateAdmin (RotateAdmin {
+                add: params[1] == 1,
+                pid: [params[2], params[3]]
+            })
         } else if command == INSTALL_PLAYER {
             Command::InstallPlayer
         } else if let Some(activity) = Activity::decode(command, &params[1..]) {
@@ -127,3 +233,25 @@ impl<Activity: SubCommand> TransactionData<Activity> {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenBalances, ERROR_INSUFFICIENT_BALANCE, ERROR_UNSUPPORTED_TOKEN, MAX_TOKENS};
+    use zkwasm_rest_abi::StorageData;
+
+    #[test]
+    fn test_token_balances() {
+        let mut balances = TokenBalances::default();
+        assert_eq!(balances.inc(2, 10), Ok(()));
+        assert_eq!(balances.get(2), 10);
+        assert_eq!(balances.get(0), 0);
+        assert_eq!(balances.cost(2, 11), Err(ERROR_INSUFFICIENT_BALANCE));
+        assert_eq!(balances.cost(5, 1), Err(ERROR_INSUFFICIENT_BALANCE));
+        assert_eq!(balances.cost(2, 4), Ok(()));
+        assert_eq!(balances.inc(MAX_TOKENS, 1), Err(ERROR_UNSUPPORTED_TOKEN));
+
+        let mut data = vec![];
+        balances.to_data(&mut data);
+        assert_eq!(data, vec![3, 0, 0, 6]);
+    }
+}
@@ -1,25 +1,44 @@
-use zkwasm_rest_abi::WithdrawInfo;
+use std::cell::RefCell;
+pub use zkwasm_rest_abi::settlement::{
+    CallInfo, NftTransferInfo, SettlementMessage, TAG_CALL, TAG_NFT_TRANSFER, TAG_WITHDRAW,
+};
+use zkwasm_rest_abi::settlement::encode_settlement;
+use zkwasm_rest_abi::{ApiOptions, WithdrawInfo};
 
 /// Settlement is flushed at the end of each bundle
-/// 1. The application transactions will push withdraw into settlement
-/// 2. All withdraw will get flushed at the end of each bundle (at the preemption point)
-pub struct SettlementInfo(Vec<WithdrawInfo>);
-pub static mut SETTLEMENT: SettlementInfo = SettlementInfo(vec![]);
+/// 1. The application transactions will push withdraw and other messages into settlement
+/// 2. All messages will get flushed at the end of each bundle (at the preemption point)
+///
+/// `flush_settlement` returns the legacy settlement of earlier versions, the 32 byte records of the
+/// withdraws. Apps that settle other messages or wide amounts return `flush_messages` from their
+/// own `flush_settlement` instead, and every message is then encoded in the `SettlementFormat` of
+/// the app, see `zkwasm_rest_abi::settlement`.
+pub struct SettlementInfo;
+
+thread_local! {
+    static SETTLEMENT: RefCell<Vec<SettlementMessage>> = RefCell::new(vec![]);
+}
 
 impl SettlementInfo {
     pub fn append_settlement(info: WithdrawInfo) {
-        unsafe { SETTLEMENT.0.push(info) };
+        Self::append_message(SettlementMessage::Withdraw(info));
+    }
+    pub fn append_message(message: SettlementMessage) {
+        SETTLEMENT.with(|s| s.borrow_mut().push(message));
+    }
+    /// Drop the messages of the bundle
+    pub fn clear() {
+        SETTLEMENT.with(|s| s.borrow_mut().clear());
     }
+    /// Take the messages of the bundle encoded as the legacy settlement, fails on messages that
+    /// it can not hold
     pub fn flush_settlement() -> Vec<u8> {
+        encode_settlement(&ApiOptions::DEFAULT, &Self::flush_messages())
+    }
+    /// Take the messages of the bundle, the generated `finalize` encodes them in the format and
+    /// commitment of the `ApiOptions` of the app
+    pub fn flush_messages() -> Vec<SettlementMessage> {
         zkwasm_rust_sdk::dbg!("flush settlement\n");
-        let sinfo = unsafe { &mut SETTLEMENT };
-        let mut bytes: Vec<u8> = Vec::with_capacity(sinfo.0.len() * 32);
-        for s in &sinfo.0 {
-            s.flush(&mut bytes);
-        }
-        sinfo.0 = vec![];
-        bytes
+        SETTLEMENT.with(|s| s.take())
     }
 }
-
-
@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
+
+use zkwasm_host_circuits::host::datahash::DataHashRecord;
+use zkwasm_host_circuits::host::db::TreeDB;
+use zkwasm_host_circuits::host::mongomerkle::MerkleRecord;
+
+use crate::history::{RootHistory, WorkingSet};
+
+pub type BoxedDB = Box<dyn TreeDB + Send + Sync>;
+
+/// A `Send + Sync` handle of the tree database shared by all http workers.
+///
+/// MongoMerkle and MongoDataHash expect an `Rc<RefCell<dyn TreeDB>>`, which can not cross threads.
+/// Each request therefore wraps a clone of this handle into a fresh `Rc` on the blocking thread that
+/// serves it (see `tree_db`). Records are content addressed by their hash so locking at record
+/// level is enough: concurrent updates never overwrite a record with different content.
+#[derive(Clone)]
+pub struct SharedDB(Arc<RwLock<BoxedDB>>);
+
+impl SharedDB {
+    pub fn new<DB: TreeDB + Send + Sync + 'static>(db: DB) -> Self {
+        SharedDB(Arc::new(RwLock::new(Box::new(db))))
+    }
+
+    /// Thread local view of the shared database that can be passed to `MongoMerkle::construct`
+    pub fn tree_db(&self) -> Option<Rc<RefCell<dyn TreeDB>>> {
+        Some(Rc::new(RefCell::new(self.clone())))
+    }
+
+    /// Run `f` while holding the database exclusively, all other requests wait until it returns.
+    /// `f` may replace the underlying database.
+    pub fn exclusive<R>(&self, f: impl FnOnce(&mut BoxedDB) -> R) -> R {
+        let mut db = self.0.write().unwrap();
+        f(&mut db)
+    }
+}
+
+impl TreeDB for SharedDB {
+    fn get_merkle_record(
+        &self,
+        record_hash: &[u8; 32],
+    ) -> Result<Option<MerkleRecord>, anyhow::Error> {
+        self.0.read().unwrap().get_merkle_record(record_hash)
+    }
+
+    fn set_merkle_record(&mut self, record: MerkleRecord) -> Result<(), anyhow::Error> {
+        self.0.write().unwrap().set_merkle_record(record)
+    }
+
+    fn set_merkle_records(&mut self, records: &Vec<MerkleRecord>) -> Result<(), anyhow::Error> {
+        self.0.write().unwrap().set_merkle_records(records)
+    }
+
+    fn get_data_record(
+        &self,
+        record_hash: &[u8; 32],
+    ) -> Result<Option<DataHashRecord>, anyhow::Error> {
+        self.0.read().unwrap().get_data_record(record_hash)
+    }
+
+    fn set_data_record(&mut self, record: DataHashRecord) -> Result<(), anyhow::Error> {
+        self.0.write().unwrap().set_data_record(record)
+    }
+}
+
+/// Placeholder that takes the slot of a database while it is closed and reopened
+pub struct ClosedDB;
+
+impl TreeDB for ClosedDB {
+    fn get_merkle_record(&self, _: &[u8; 32]) -> Result<Option<MerkleRecord>, anyhow::Error> {
+        Err(anyhow::anyhow!("database is closed"))
+    }
+
+    fn set_merkle_record(&mut self, _: MerkleRecord) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("database is closed"))
+    }
+
+    fn set_merkle_records(&mut self, _: &Vec<MerkleRecord>) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("database is closed"))
+    }
+
+    fn get_data_record(&self, _: &[u8; 32]) -> Result<Option<DataHashRecord>, anyhow::Error> {
+        Err(anyhow::anyhow!("database is closed"))
+    }
+
+    fn set_data_record(&mut self, _: DataHashRecord) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("database is closed"))
+    }
+}
+
+/// State shared by all RPC handlers through `jsonrpc_v2::Data`
+pub struct AppState {
+    pub db: SharedDB,
+    /// Location of the RocksDB directory, needed to rebuild it during garbage collection
+    pub uri: PathBuf,
+    pub history: Mutex<RootHistory>,
+    pub working: Mutex<WorkingSet>,
+    /// Held shared by every update and exclusively by garbage collection, so that the nodes of an
+    /// update are either all written before the collection or all after it
+    pub updates: RwLock<()>,
+    /// Directory of the snapshots written by `export_snapshot`
+    pub snapshot_dir: Option<PathBuf>,
+}
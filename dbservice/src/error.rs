@@ -0,0 +1,76 @@
+use jsonrpc_v2::Error;
+
+/// JSON-RPC error codes returned by csm_service. `INVALID_PARAMS` follows the JSON-RPC 2.0 spec and
+/// the remaining codes live in the implementation defined server error range.
+pub const INVALID_PARAMS: i64 = -32602;
+pub const UNKNOWN_ROOT: i64 = -32001;
+pub const MISSING_RECORD: i64 = -32002;
+pub const STORAGE_FAILURE: i64 = -32003;
+
+pub const LEAF_INDEX_START: u64 = (1u64 << crate::MERKLE_DEPTH) - 1;
+pub const LEAF_INDEX_END: u64 = (1u64 << (crate::MERKLE_DEPTH + 1)) - 1;
+
+#[derive(Debug)]
+pub enum ServiceError {
+    /// The request is malformed, retrying it will not help
+    InvalidParams(String),
+    /// The requested root is not present in the database
+    UnknownRoot([u8; 32]),
+    /// A node or record that is reachable from a known root is missing, the database is corrupted
+    MissingRecord(String),
+    /// The storage backend failed to serve the request
+    Storage(String),
+}
+
+impl ServiceError {
+    pub fn code(&self) -> i64 {
+        match self {
+            ServiceError::InvalidParams(_) => INVALID_PARAMS,
+            ServiceError::UnknownRoot(_) => UNKNOWN_ROOT,
+            ServiceError::MissingRecord(_) => MISSING_RECORD,
+            ServiceError::Storage(_) => STORAGE_FAILURE,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ServiceError::InvalidParams(m) => format!("invalid params: {}", m),
+            ServiceError::UnknownRoot(root) => format!("unknown root: {}", hex::encode(root)),
+            ServiceError::MissingRecord(m) => format!("missing record: {}", m),
+            ServiceError::Storage(m) => format!("storage failure: {}", m),
+        }
+    }
+}
+
+impl From<ServiceError> for Error {
+    fn from(e: ServiceError) -> Self {
+        println!("rpc error {}", e.message());
+        Error::Full {
+            code: e.code(),
+            message: e.message(),
+            data: None,
+        }
+    }
+}
+
+pub fn parse_u64(field: &str, v: &str) -> Result<u64, ServiceError> {
+    u64::from_str_radix(v, 10)
+        .map_err(|_| ServiceError::InvalidParams(format!("{} is not a u64: {:?}", field, v)))
+}
+
+/// Parse the decimal encoding of a leaf index and make sure it addresses a leaf of the tree
+pub fn parse_leaf_index(v: &str) -> Result<u64, ServiceError> {
+    let index = parse_u64("index", v)?;
+    if index < LEAF_INDEX_START || index >= LEAF_INDEX_END {
+        Err(ServiceError::InvalidParams(format!(
+            "index {} is not a leaf index",
+            index
+        )))
+    } else {
+        Ok(index)
+    }
+}
+
+pub fn blocking_error<E: std::fmt::Debug>(e: E) -> ServiceError {
+    ServiceError::Storage(format!("blocking task failed: {:?}", e))
+}
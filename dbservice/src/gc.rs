@@ -0,0 +1,335 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use zkwasm_host_circuits::host::db::RocksDB;
+use zkwasm_host_circuits::host::db::TreeDB;
+
+use crate::db::{BoxedDB, ClosedDB};
+use crate::error::ServiceError;
+use crate::walk::{Visit, Walker};
+
+/// Rough size of a stored merkle record (index, hash, left, right and data), used to estimate the
+/// reclaimable space without rewriting the database.
+const MERKLE_RECORD_BYTES: u64 = 8 + 32 * 4;
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    pub live_roots: usize,
+    pub live_merkle_records: usize,
+    pub live_data_records: usize,
+    /// estimated size of the live records, without the storage overhead of rocksdb
+    pub live_bytes: u64,
+    /// size of the database directory before collection
+    pub disk_bytes: u64,
+    /// estimated by `report`, measured by `collect`
+    pub reclaimable_bytes: u64,
+}
+
+fn storage<E: std::fmt::Debug>(e: E) -> ServiceError {
+    ServiceError::Storage(format!("{:?}", e))
+}
+
+pub fn open_rocksdb(path: &Path) -> Result<RocksDB, ServiceError> {
+    RocksDB::new(path.to_string_lossy().to_string()).map_err(storage)
+}
+
+pub fn dir_size(path: &Path) -> u64 {
+    std::fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .filter_map(|e| e.ok())
+            .map(|e| match e.metadata() {
+                Ok(m) if m.is_dir() => dir_size(&e.path()),
+                Ok(m) => m.len(),
+                Err(_) => 0,
+            })
+            .sum()
+    })
+}
+
+fn gc_path(uri: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.gc", uri.display()))
+}
+
+fn old_path(uri: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.old", uri.display()))
+}
+
+/// Finish or roll back a collection that was interrupted, before the database at `uri` is opened.
+/// If the service stopped between the two renames of `collect` the old database is restored,
+/// otherwise the leftover copy and old database are removed.
+pub fn recover(uri: &Path) -> Result<(), ServiceError> {
+    let tmp = gc_path(uri);
+    let old = old_path(uri);
+    if !uri.exists() && old.exists() {
+        std::fs::rename(&old, uri).map_err(storage)?;
+    }
+    for leftover in [tmp, old] {
+        if leftover.exists() {
+            std::fs::remove_dir_all(&leftover).map_err(storage)?;
+        }
+    }
+    Ok(())
+}
+
+/// Walk the trees of `roots` and the data records `records`
+fn walk_live<F>(walker: &mut Walker, roots: &[[u8; 32]], records: &[[u8; 32]], visit: &mut F) -> Result<(), ServiceError>
+where
+    F: FnMut(Visit) -> Result<(), ServiceError>,
+{
+    for root in roots {
+        walker.walk(*root, visit)?;
+    }
+    for record in records {
+        walker.walk_data(*record, visit)?;
+    }
+    Ok(())
+}
+
+/// Mark everything that is reachable from `roots` or `records` and estimate how much space a
+/// collection would free. The database is not modified.
+pub fn report(db: &dyn TreeDB, uri: &Path, roots: &[[u8; 32]], records: &[[u8; 32]]) -> Result<GcReport, ServiceError> {
+    let mut live_bytes = 0;
+    let mut walker = Walker::new(db, true);
+    walk_live(&mut walker, roots, records, &mut |v| {
+        match v {
+            Visit::Node(_) => live_bytes += MERKLE_RECORD_BYTES,
+            Visit::Data(r) => live_bytes += 32 + r.data.len() as u64,
+            Visit::Leaf { .. } => (),
+        };
+        Ok(())
+    })?;
+    let disk_bytes = dir_size(uri);
+    Ok(GcReport {
+        live_roots: roots.len(),
+        live_merkle_records: walker.merkle.len(),
+        live_data_records: walker.data.len(),
+        live_bytes,
+        disk_bytes,
+        reclaimable_bytes: disk_bytes.saturating_sub(live_bytes),
+    })
+}
+
+/// Copy everything reachable from `roots` or `records` into a fresh database and replace the
+/// database at `uri` with the copy. The caller must hold `db` exclusively, it is closed and
+/// reopened in place.
+///
+/// The copy is built in `<uri>.gc`, then `<uri>` is moved to `<uri>.old` and the copy to `<uri>`
+/// before the old database is deleted, so that a crash at any point leaves a complete database
+/// behind for `recover`. If the copy can not be moved in place or reopened, the original database
+/// is moved back and reopened before the error is returned.
+pub fn collect(db: &mut BoxedDB, uri: &Path, roots: &[[u8; 32]], records: &[[u8; 32]]) -> Result<GcReport, ServiceError> {
+    if roots.is_empty() {
+        return Err(ServiceError::InvalidParams(
+            "no root is committed or pinned, refuse to collect everything".to_string(),
+        ));
+    }
+    let tmp = gc_path(uri);
+    if tmp.exists() {
+        std::fs::remove_dir_all(&tmp).map_err(storage)?;
+    }
+
+    let mut report = GcReport {
+        live_roots: roots.len(),
+        disk_bytes: dir_size(uri),
+        ..GcReport::default()
+    };
+    {
+        let mut dst = open_rocksdb(&tmp)?;
+        let mut nodes = vec![];
+        let mut walker = Walker::new(&**db, true);
+        walk_live(&mut walker, roots, records, &mut |v| {
+            match v {
+                Visit::Node(r) => {
+                    report.live_bytes += MERKLE_RECORD_BYTES;
+                    nodes.push(r.clone());
+                    if nodes.len() == BATCH_SIZE {
+                        dst.set_merkle_records(&nodes).map_err(storage)?;
+                        nodes.clear();
+                    }
+                }
+                Visit::Data(r) => {
+                    report.live_bytes += 32 + r.data.len() as u64;
+                    dst.set_data_record(r.clone()).map_err(storage)?;
+                }
+                Visit::Leaf { .. } => (),
+            };
+            Ok(())
+        })?;
+        dst.set_merkle_records(&nodes).map_err(storage)?;
+        report.live_merkle_records = walker.merkle.len();
+        report.live_data_records = walker.data.len();
+    }
+
+    swap(db, uri, open_rocksdb)?;
+    report.reclaimable_bytes = report.disk_bytes.saturating_sub(dir_size(uri));
+    Ok(report)
+}
+
+/// Replace the database at `uri` with the copy in `<uri>.gc` and reopen it with `open`. If any
+/// step fails the original database is moved back to `uri` and reopened, so that `db` is never
+/// left closed.
+fn swap<F>(db: &mut BoxedDB, uri: &Path, open: F) -> Result<(), ServiceError>
+where
+    F: Fn(&Path) -> Result<RocksDB, ServiceError>,
+{
+    let tmp = gc_path(uri);
+    let old = old_path(uri);
+    // close the current database before swapping the directories
+    drop(std::mem::replace(db, Box::new(ClosedDB)));
+    let swapped = (|| {
+        if old.exists() {
+            std::fs::remove_dir_all(&old).map_err(storage)?;
+        }
+        std::fs::rename(uri, &old).map_err(storage)?;
+        std::fs::rename(&tmp, uri).map_err(storage)?;
+        open(uri)
+    })();
+    match swapped {
+        Ok(copy) => {
+            *db = Box::new(copy);
+            std::fs::remove_dir_all(&old).map_err(storage)
+        }
+        Err(e) => {
+            if old.exists() {
+                if uri.exists() {
+                    std::fs::remove_dir_all(uri).map_err(storage)?;
+                }
+                std::fs::rename(&old, uri).map_err(storage)?;
+            }
+            if tmp.exists() {
+                std::fs::remove_dir_all(&tmp).map_err(storage)?;
+            }
+            *db = Box::new(open_rocksdb(uri)?);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zkwasm_host_circuits::host::datahash::{DataHashRecord, MongoDataHash};
+    use zkwasm_host_circuits::host::db::TreeDB;
+    use zkwasm_host_circuits::host::merkle::MerkleTree;
+    use zkwasm_host_circuits::host::mongomerkle::MongoMerkle;
+
+    use super::{collect, gc_path, old_path, open_rocksdb, recover, swap};
+    use crate::error::ServiceError;
+    use crate::db::SharedDB;
+    use crate::history::WorkingSet;
+    use crate::walk::default_hashes;
+    use crate::MERKLE_DEPTH;
+
+    const LEAF: u64 = (1u64 << MERKLE_DEPTH) - 1;
+
+    fn update(db: &SharedDB, root: [u8; 32], index: u64, data: [u8; 32]) -> [u8; 32] {
+        let mut mt = MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], root, db.tree_db());
+        mt.update_leaf_data_with_proof(index, &data.to_vec()).unwrap();
+        mt.get_root_hash()
+    }
+
+    fn leaf(db: &SharedDB, root: [u8; 32], index: u64) -> [u8; 32] {
+        let mt = MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], root, db.tree_db());
+        mt.get_leaf_with_proof(index).unwrap().0.data.unwrap_or([0; 32])
+    }
+
+    fn record(db: &SharedDB, hash: [u8; 32], data: Vec<u8>) {
+        let mut datahash = MongoDataHash::construct([0; 32], db.tree_db());
+        datahash.update_record(DataHashRecord { hash, data }).unwrap();
+    }
+
+    fn has_record(db: &SharedDB, hash: [u8; 32]) -> bool {
+        db.get_data_record(&hash).unwrap().is_some()
+    }
+
+    #[test]
+    fn test_collect() {
+        let dir = tempfile::tempdir().unwrap();
+        let uri = dir.path().join("db");
+        let db = SharedDB::new(open_rocksdb(&uri).unwrap());
+        let mut working = WorkingSet::new(2);
+
+        // data record [1] references [2] at an aligned offset and [3] at an unaligned one, both
+        // are kept
+        let mut data = vec![0u8; 8];
+        data.extend_from_slice(&[2; 32]);
+        data.extend_from_slice(&[0; 3]);
+        data.extend_from_slice(&[3; 32]);
+        data.extend_from_slice(&[0; 5]);
+        record(&db, [1; 32], data);
+        record(&db, [2; 32], vec![2; 16]);
+        record(&db, [3; 32], vec![3; 16]);
+        record(&db, [4; 32], vec![4; 16]);
+
+        let committed = update(&db, default_hashes()[0], LEAF, [1; 32]);
+        working.add_root(committed);
+        working.commit(committed);
+        // the sequencer keeps building on the committed root without committing
+        let uncommitted = update(&db, committed, LEAF + 1, [5; 32]);
+        working.add_root(uncommitted);
+        record(&db, [6; 32], vec![6; 16]);
+        working.add_record([6; 32]);
+        // an abandoned branch
+        let garbage = update(&db, committed, LEAF + 2, [7; 32]);
+
+        let mut roots = vec![committed];
+        roots.extend(working.roots());
+        let report = db
+            .exclusive(|db| collect(db, &uri, &roots, &working.records()))
+            .unwrap();
+        assert_eq!(report.live_roots, 2);
+        assert_eq!(report.live_data_records, 4);
+
+        assert_eq!(leaf(&db, committed, LEAF), [1; 32]);
+        assert_eq!(leaf(&db, uncommitted, LEAF), [1; 32]);
+        assert_eq!(leaf(&db, uncommitted, LEAF + 1), [5; 32]);
+        assert!(db.get_merkle_record(&garbage).unwrap().is_none());
+        assert!(has_record(&db, [1; 32]));
+        assert!(has_record(&db, [2; 32]));
+        assert!(has_record(&db, [3; 32]));
+        assert!(!has_record(&db, [4; 32]));
+        assert!(has_record(&db, [6; 32]));
+        assert!(!gc_path(&uri).exists());
+        assert!(!old_path(&uri).exists());
+    }
+
+    #[test]
+    fn test_collect_reopen_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let uri = dir.path().join("db");
+        let db = SharedDB::new(open_rocksdb(&uri).unwrap());
+        record(&db, [1; 32], vec![1; 16]);
+        std::fs::create_dir_all(gc_path(&uri)).unwrap();
+
+        let result = db.exclusive(|db| {
+            swap(db, &uri, |_| Err(ServiceError::Storage("reopen failed".to_string())))
+        });
+        assert!(result.is_err());
+        // the original database is back in place and open
+        assert!(has_record(&db, [1; 32]));
+        record(&db, [2; 32], vec![2; 16]);
+        assert!(has_record(&db, [2; 32]));
+        assert!(!gc_path(&uri).exists());
+        assert!(!old_path(&uri).exists());
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let uri = dir.path().join("db");
+
+        // interrupted between the two renames: the old database is restored
+        std::fs::create_dir_all(old_path(&uri).join("old")).unwrap();
+        std::fs::create_dir_all(gc_path(&uri).join("copy")).unwrap();
+        recover(&uri).unwrap();
+        assert!(uri.join("old").exists());
+        assert!(!gc_path(&uri).exists());
+        assert!(!old_path(&uri).exists());
+
+        // interrupted before the old database was deleted: the copy is kept
+        std::fs::create_dir_all(old_path(&uri)).unwrap();
+        recover(&uri).unwrap();
+        assert!(uri.join("old").exists());
+        assert!(!old_path(&uri).exists());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ServiceError;
+
+/// Roots that must survive garbage collection.
+///
+/// Every `update_leaf` creates a new root, but only the roots of finished bundles and the roots
+/// referenced by proofs that are not settled yet are worth keeping. The sequencer reports the
+/// former through `commit_root` (only the latest `keep` are retained) and pins the latter with
+/// `pin_root`/`unpin_root`. The history is persisted as json next to the database.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RootHistory {
+    #[serde(skip)]
+    path: PathBuf,
+    pub keep: usize,
+    /// the latest bundle roots, oldest first
+    pub recent: VecDeque<[u8; 32]>,
+    /// hex encoded pinned roots with their pin count
+    pub pinned: BTreeMap<String, usize>,
+}
+
+impl RootHistory {
+    pub fn load(path: &Path, keep: usize) -> std::io::Result<Self> {
+        let mut history = if path.exists() {
+            let content = std::fs::read_to_string(path)?;
+            serde_json::from_str::<RootHistory>(&content)?
+        } else {
+            RootHistory::default()
+        };
+        history.path = path.to_path_buf();
+        history.keep = keep;
+        history.trim();
+        Ok(history)
+    }
+
+    /// Write the history to a temporary file next to it and rename it over the previous one, so
+    /// that a crash while saving leaves either the old or the new history behind
+    fn save(&self) -> Result<(), ServiceError> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        let tmp = PathBuf::from(format!("{}.tmp", self.path.display()));
+        std::fs::write(&tmp, content)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ServiceError::Storage(format!("can not save root history: {:?}", e)))
+    }
+
+    fn trim(&mut self) {
+        while self.recent.len() > self.keep {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn commit(&mut self, root: [u8; 32]) -> Result<(), ServiceError> {
+        self.recent.push_back(root);
+        self.trim();
+        self.save()
+    }
+
+    pub fn pin(&mut self, root: [u8; 32]) -> Result<usize, ServiceError> {
+        let count = self.pinned.entry(hex::encode(root)).or_insert(0);
+        *count += 1;
+        let count = *count;
+        self.save()?;
+        Ok(count)
+    }
+
+    pub fn unpin(&mut self, root: [u8; 32]) -> Result<usize, ServiceError> {
+        let key = hex::encode(root);
+        let count = match self.pinned.get_mut(&key) {
+            None => {
+                return Err(ServiceError::InvalidParams(format!(
+                    "root {} is not pinned",
+                    key
+                )))
+            }
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+        };
+        if count == 0 {
+            self.pinned.remove(&key);
+        }
+        self.save()?;
+        Ok(count)
+    }
+
+    /// All roots that are still referenced, without duplicates
+    pub fn live_roots(&self) -> Result<Vec<[u8; 32]>, ServiceError> {
+        let mut roots = self.recent.iter().cloned().collect::<Vec<_>>();
+        for key in self.pinned.keys() {
+            let root = hex::decode(key)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| {
+                    ServiceError::Storage(format!("malformed pinned root {:?} in root history", key))
+                })?;
+            roots.push(root);
+        }
+        roots.sort();
+        roots.dedup();
+        Ok(roots)
+    }
+}
+
+/// Roots and data records written since they were last committed.
+///
+/// The sequencer builds each bundle on top of the latest committed root and only commits the root
+/// of the bundle once the bundle is finished, so the roots in between are only known here. Online
+/// garbage collection keeps them alive together with the data records written through
+/// `update_record`. Every write is numbered, and committing a root drops it together with
+/// everything written before it, which the committed root supersedes. Commits of roots that were
+/// not written here still move the window: whatever was written before a commit that is no longer
+/// among the latest `keep` commits is dropped as well, so the set stays bounded when commits never
+/// match. The working set lives in memory only: an offline collection keeps nothing but the
+/// committed and pinned roots.
+#[derive(Debug, Default)]
+pub struct WorkingSet {
+    seq: u64,
+    keep: usize,
+    /// the sequence number at each of the latest `keep` commits, oldest first
+    commits: VecDeque<u64>,
+    roots: HashMap<[u8; 32], u64>,
+    records: HashMap<[u8; 32], u64>,
+}
+
+impl WorkingSet {
+    pub fn new(keep: usize) -> Self {
+        WorkingSet {
+            keep,
+            ..WorkingSet::default()
+        }
+    }
+
+    fn drop_until(&mut self, seq: u64) {
+        self.roots.retain(|_, s| *s > seq);
+        self.records.retain(|_, s| *s > seq);
+    }
+
+    pub fn add_root(&mut self, root: [u8; 32]) {
+        self.seq += 1;
+        self.roots.insert(root, self.seq);
+    }
+
+    pub fn add_record(&mut self, hash: [u8; 32]) {
+        self.seq += 1;
+        self.records.insert(hash, self.seq);
+    }
+
+    pub fn commit(&mut self, root: [u8; 32]) {
+        if let Some(seq) = self.roots.get(&root).copied() {
+            self.drop_until(seq);
+        }
+        self.commits.push_back(self.seq);
+        while self.commits.len() > self.keep.max(1) {
+            let seq = self.commits.pop_front().unwrap();
+            self.drop_until(seq);
+        }
+    }
+
+    pub fn roots(&self) -> Vec<[u8; 32]> {
+        self.roots.keys().cloned().collect()
+    }
+
+    pub fn records(&self) -> Vec<[u8; 32]> {
+        self.records.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RootHistory, WorkingSet};
+
+    #[test]
+    fn test_root_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots.json");
+        let mut history = RootHistory::load(&path, 2).unwrap();
+        history.commit([1; 32]).unwrap();
+        history.commit([2; 32]).unwrap();
+        history.commit([3; 32]).unwrap();
+        assert_eq!(history.pin([1; 32]).unwrap(), 1);
+        assert_eq!(history.pin([1; 32]).unwrap(), 2);
+        assert_eq!(history.live_roots().unwrap(), vec![[1; 32], [2; 32], [3; 32]]);
+
+        let mut history = RootHistory::load(&path, 1).unwrap();
+        assert_eq!(history.live_roots().unwrap(), vec![[1; 32], [3; 32]]);
+        assert_eq!(history.unpin([1; 32]).unwrap(), 1);
+        assert_eq!(history.unpin([1; 32]).unwrap(), 0);
+        assert!(history.unpin([1; 32]).is_err());
+        assert_eq!(history.live_roots().unwrap(), vec![[3; 32]]);
+        assert!(!dir.path().join("roots.json.tmp").exists());
+
+        history.pinned.insert("not a root".to_string(), 1);
+        assert!(history.live_roots().is_err());
+    }
+
+    #[test]
+    fn test_working_set() {
+        let mut working = WorkingSet::new(2);
+        working.add_root([1; 32]);
+        working.add_record([7; 32]);
+        working.add_root([2; 32]);
+        working.add_record([8; 32]);
+        working.add_root([3; 32]);
+
+        // committing a root that was not written here keeps everything
+        working.commit([9; 32]);
+        assert_eq!(working.roots().len(), 3);
+
+        working.commit([2; 32]);
+        assert_eq!(working.roots(), vec![[3; 32]]);
+        assert_eq!(working.records(), vec![[8; 32]]);
+        working.commit([3; 32]);
+        assert!(working.roots().is_empty());
+        assert!(working.records().is_empty());
+    }
+
+    #[test]
+    fn test_working_set_window() {
+        let mut working = WorkingSet::new(2);
+        working.add_root([1; 32]);
+        working.add_record([7; 32]);
+        // commits of roots written elsewhere
+        working.commit([8; 32]);
+        working.add_root([2; 32]);
+        working.commit([9; 32]);
+        assert_eq!(working.roots().len(), 2);
+        // the writes before the oldest retained commit are dropped
+        working.commit([10; 32]);
+        assert_eq!(working.roots(), vec![[2; 32]]);
+        assert!(working.records().is_empty());
+    }
+}
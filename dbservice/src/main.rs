@@ -1,6 +1,3 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
 use jsonrpc_v2::{Data, Error, Params, Server};
 use serde::Deserialize;
 use serde::Serialize;
@@ -8,15 +5,29 @@ use zkwasm_host_circuits::host::datahash::DataHashRecord;
 use zkwasm_host_circuits::host::datahash::MongoDataHash;
 use zkwasm_host_circuits::host::db::RocksDB;
 use zkwasm_host_circuits::host::db::TreeDB;
+use zkwasm_host_circuits::host::merkle::MerkleNode;
+use zkwasm_host_circuits::host::merkle::MerkleProof;
 use zkwasm_host_circuits::host::merkle::MerkleTree;
+use zkwasm_host_circuits::host::mongomerkle::MerkleRecord;
 use zkwasm_host_circuits::host::mongomerkle::MongoMerkle;
 use zkwasm_host_circuits::proof::MERKLE_DEPTH;
 
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
 use std::time::Instant;
 
-//use tokio::runtime::Runtime;
-
-static mut DB: Option<Rc<RefCell<dyn TreeDB>>> = None;
+mod db;
+mod error;
+mod gc;
+mod history;
+mod snapshot;
+mod walk;
+use db::{AppState, SharedDB};
+use error::{blocking_error, parse_leaf_index, parse_u64, ServiceError};
+use gc::GcReport;
+use history::{RootHistory, WorkingSet};
+use snapshot::SnapshotSummary;
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct UpdateLeafRequest {
@@ -30,6 +41,34 @@ pub struct GetLeafRequest {
     index: String,
 }
 
+/// Inclusion proof of a leaf. `assist` holds the MERKLE_DEPTH sibling hashes ordered from the
+/// leaf level up to the children of the root.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LeafProof {
+    root: [u8; 32],
+    index: String,
+    data: [u8; 32],
+    assist: Vec<[u8; 32]>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LeafUpdate {
+    data: [u8; 32],
+    index: String, // u64 encoding
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct UpdateLeavesRequest {
+    root: [u8; 32],
+    leaves: Vec<LeafUpdate>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct GetLeavesRequest {
+    root: [u8; 32],
+    index: Vec<String>,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct UpdateRecordRequest {
     hash: [u8; 32],
@@ -40,90 +79,365 @@ pub struct GetRecordRequest {
     hash: [u8; 32],
 }
 
-fn get_mt(root: [u8; 32]) -> MongoMerkle<32> {
-    MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], root, unsafe { DB.clone() })
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RootRequest {
+    root: [u8; 32],
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ExportSnapshotRequest {
+    root: [u8; 32],
+    path: String, // file relative to --snapshot-dir on the machine running the service
 }
 
-async fn update_leaf(Params(request): Params<UpdateLeafRequest>) -> Result<[u8; 32], Error> {
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RootList {
+    recent: Vec<[u8; 32]>,
+    pinned: Vec<([u8; 32], usize)>,
+}
+
+fn get_mt(db: &SharedDB, root: [u8; 32]) -> MongoMerkle<MERKLE_DEPTH> {
+    MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], root, db.tree_db())
+}
+
+lazy_static::lazy_static! {
+    /// The root of the empty tree is never stored but is always known
+    static ref EMPTY_ROOT: [u8; 32] = walk::default_hashes()[0];
+}
+
+/// Construct the tree of `root` and make sure the root node can be resolved, so that a later
+/// failure while walking the tree can be reported as a missing record instead of an unknown root.
+/// Only a root that is absent from the database is unknown, a failing lookup is a storage error.
+fn get_known_mt(db: &SharedDB, root: [u8; 32]) -> Result<MongoMerkle<MERKLE_DEPTH>, ServiceError> {
+    if root != *EMPTY_ROOT {
+        db.get_merkle_record(&root)
+            .map_err(|e| ServiceError::Storage(format!("{:?}", e)))?
+            .ok_or(ServiceError::UnknownRoot(root))?;
+    }
+    Ok(get_mt(db, root))
+}
+
+fn missing_record<E: std::fmt::Debug>(e: E) -> ServiceError {
+    ServiceError::MissingRecord(format!("{:?}", e))
+}
+
+async fn update_leaf(
+    state: Data<AppState>,
+    Params(request): Params<UpdateLeafRequest>,
+) -> Result<[u8; 32], Error> {
     let start = Instant::now();
-    let index = u64::from_str_radix(request.index.as_str(), 10).unwrap();
+    let index = parse_leaf_index(request.index.as_str())?;
     let hash = actix_web::web::block(move || {
-        let mut mt = get_mt(request.root);
+        let _updates = state.updates.read().unwrap();
+        let mut mt = get_known_mt(&state.db, request.root)?;
         mt.update_leaf_data_with_proof(index, &request.data.to_vec())
-            .map_err(|e| {
-                println!("update leaf data with proof error {:?}", e);
-                Error::INTERNAL_ERROR
-            })?;
-        Ok(mt.get_root_hash())
+            .map_err(missing_record)?;
+        let hash = mt.get_root_hash();
+        state.working.lock().unwrap().add_root(hash);
+        Ok::<_, ServiceError>(hash)
     })
     .await
-    .map_err(|_| Error::INTERNAL_ERROR)?;
+    .map_err(blocking_error)??;
     let duration = start.elapsed();
     println!("time taken for update_leaf is {:?}", duration);
-    hash
+    Ok(hash)
 }
 
-async fn get_leaf(Params(request): Params<GetLeafRequest>) -> Result<[u8; 32], Error> {
+async fn get_leaf(
+    state: Data<AppState>,
+    Params(request): Params<GetLeafRequest>,
+) -> Result<[u8; 32], Error> {
     let start = Instant::now();
-    let index = u64::from_str_radix(request.index.as_str(), 10).unwrap();
+    let index = parse_leaf_index(request.index.as_str())?;
     let leaf = actix_web::web::block(move || {
-        let mt = get_mt(request.root);
-        let (leaf, _) = mt.get_leaf_with_proof(index).map_err(|e| {
-            println!("get leaf error {:?}", e);
-            Error::INTERNAL_ERROR
-        })?;
-        Ok(leaf)
+        let mt = get_known_mt(&state.db, request.root)?;
+        let (leaf, _) = mt.get_leaf_with_proof(index).map_err(missing_record)?;
+        Ok::<_, ServiceError>(leaf)
     })
     .await
-    .map_err(|_| Error::INTERNAL_ERROR)?;
+    .map_err(blocking_error)??;
     let duration = start.elapsed();
     println!("time taken for get_leaf is {:?}", duration);
-    leaf.map(|l| {
-        l.data.unwrap_or([0; 32])
+    Ok(leaf.data.unwrap_or([0; 32]))
+}
+
+async fn get_leaf_proof(
+    state: Data<AppState>,
+    Params(request): Params<GetLeafRequest>,
+) -> Result<LeafProof, Error> {
+    let start = Instant::now();
+    let index = parse_leaf_index(request.index.as_str())?;
+    let proof = actix_web::web::block(move || {
+        let mt = get_known_mt(&state.db, request.root)?;
+        let (leaf, proof) = mt.get_leaf_with_proof(index).map_err(missing_record)?;
+        Ok::<_, ServiceError>(LeafProof {
+            root: proof.root,
+            index: proof.index.to_string(),
+            data: leaf.data.unwrap_or([0; 32]),
+            assist: proof.assist.to_vec(),
+        })
+    })
+    .await
+    .map_err(blocking_error)??;
+    let duration = start.elapsed();
+    println!("time taken for get_leaf_proof is {:?}", duration);
+    Ok(proof)
+}
+
+/// Check a proof returned by `get_leaf_proof` against the root it carries. The leaf hash is
+/// recomputed from `data` so the caller does not need to trust any hash from the service.
+async fn verify_leaf_proof(
+    state: Data<AppState>,
+    Params(request): Params<LeafProof>,
+) -> Result<bool, Error> {
+    let index = parse_leaf_index(request.index.as_str())?;
+    let depth = request.assist.len();
+    let assist: [[u8; 32]; MERKLE_DEPTH] = request.assist.try_into().map_err(|_| {
+        ServiceError::InvalidParams(format!(
+            "proof has {} siblings, expect {}",
+            depth, MERKLE_DEPTH
+        ))
+    })?;
+    let mut leaf = MerkleRecord::new(index);
+    leaf.set(&request.data.to_vec());
+    let proof = MerkleProof::<[u8; 32], MERKLE_DEPTH> {
+        source: leaf.hash(),
+        root: request.root,
+        assist,
+        index,
+    };
+    let valid = actix_web::web::block(move || {
+        let mt = get_mt(&state.db, request.root);
+        mt.verify_proof(&proof)
+            .map_err(|e| ServiceError::InvalidParams(format!("{:?}", e)))
+    })
+    .await
+    .map_err(blocking_error)??;
+    Ok(valid)
+}
+
+/// Apply a list of leaf updates in order against a single tree. The returned vector contains the
+/// root after each update, so its last element is the final root.
+async fn update_leaves(
+    state: Data<AppState>,
+    Params(request): Params<UpdateLeavesRequest>,
+) -> Result<Vec<[u8; 32]>, Error> {
+    let start = Instant::now();
+    let updates = request
+        .leaves
+        .iter()
+        .map(|l| Ok((parse_leaf_index(l.index.as_str())?, l.data)))
+        .collect::<Result<Vec<_>, ServiceError>>()?;
+    let size = updates.len();
+    let roots = actix_web::web::block(move || {
+        let _updates = state.updates.read().unwrap();
+        let mut mt = get_known_mt(&state.db, request.root)?;
+        let mut roots = Vec::with_capacity(updates.len());
+        for (index, data) in updates {
+            mt.update_leaf_data_with_proof(index, &data.to_vec())
+                .map_err(missing_record)?;
+            roots.push(mt.get_root_hash());
+        }
+        let mut working = state.working.lock().unwrap();
+        for root in roots.iter() {
+            working.add_root(*root);
+        }
+        Ok::<_, ServiceError>(roots)
     })
+    .await
+    .map_err(blocking_error)??;
+    let duration = start.elapsed();
+    println!(
+        "time taken for update_leaves ({} leaves) is {:?}",
+        size, duration
+    );
+    Ok(roots)
+}
+
+async fn get_leaves(
+    state: Data<AppState>,
+    Params(request): Params<GetLeavesRequest>,
+) -> Result<Vec<[u8; 32]>, Error> {
+    let start = Instant::now();
+    let indexes = request
+        .index
+        .iter()
+        .map(|i| parse_leaf_index(i.as_str()))
+        .collect::<Result<Vec<_>, ServiceError>>()?;
+    let size = indexes.len();
+    let leaves = actix_web::web::block(move || {
+        let mt = get_known_mt(&state.db, request.root)?;
+        indexes
+            .into_iter()
+            .map(|index| {
+                let (leaf, _) = mt.get_leaf_with_proof(index).map_err(missing_record)?;
+                Ok(leaf.data.unwrap_or([0; 32]))
+            })
+            .collect::<Result<Vec<_>, ServiceError>>()
+    })
+    .await
+    .map_err(blocking_error)??;
+    let duration = start.elapsed();
+    println!(
+        "time taken for get_leaves ({} leaves) is {:?}",
+        size, duration
+    );
+    Ok(leaves)
 }
-async fn update_record(Params(request): Params<UpdateRecordRequest>) -> Result<(), Error> {
-    let _ = actix_web::web::block(move || {
-        let mut mongo_datahash = MongoDataHash::construct([0; 32], unsafe { DB.clone() });
-        mongo_datahash.update_record({
-            DataHashRecord {
+
+async fn update_record(
+    state: Data<AppState>,
+    Params(request): Params<UpdateRecordRequest>,
+) -> Result<(), Error> {
+    let data = request
+        .data
+        .iter()
+        .map(|x| Ok(parse_u64("data", x)?.to_le_bytes()))
+        .collect::<Result<Vec<_>, ServiceError>>()?
+        .concat();
+    actix_web::web::block(move || {
+        let _updates = state.updates.read().unwrap();
+        let mut mongo_datahash = MongoDataHash::construct([0; 32], state.db.tree_db());
+        mongo_datahash
+            .update_record(DataHashRecord {
                 hash: request.hash,
-                data: request
-                    .data
-                    .iter()
-                    .map(|x| {
-                        let x = u64::from_str_radix(x, 10).unwrap();
-                        x.to_le_bytes()
-                    })
-                    .flatten()
-                    .collect::<Vec<u8>>(),
-            }
-        })
+                data,
+            })
+            .map_err(|e| ServiceError::Storage(format!("{:?}", e)))?;
+        state.working.lock().unwrap().add_record(request.hash);
+        Ok::<_, ServiceError>(())
     })
     .await
-    .map_err(|_| Error::INTERNAL_ERROR)?;
+    .map_err(blocking_error)??;
     Ok(())
 }
 
-async fn get_record(Params(request): Params<GetRecordRequest>) -> Result<Vec<String>, Error> {
+/// Records that do not exist are returned as an empty vector, the cache protocol of the host
+/// treats them as empty data.
+async fn get_record(
+    state: Data<AppState>,
+    Params(request): Params<GetRecordRequest>,
+) -> Result<Vec<String>, Error> {
     let datahashrecord = actix_web::web::block(move || {
-        let mongo_datahash = MongoDataHash::construct([0; 32], unsafe { DB.clone() });
-        mongo_datahash.get_record(&request.hash).unwrap()
+        let mongo_datahash = MongoDataHash::construct([0; 32], state.db.tree_db());
+        mongo_datahash
+            .get_record(&request.hash)
+            .map_err(|e| ServiceError::Storage(format!("{:?}", e)))
     })
     .await
-    .map_err(|_| Error::INTERNAL_ERROR)?;
+    .map_err(blocking_error)??;
     let data = datahashrecord.map_or(vec![], |r| {
         r.data
             .chunks_exact(8)
             .into_iter()
-            .into_iter()
             .map(|x| u64::from_le_bytes(x.try_into().unwrap()).to_string())
             .collect::<Vec<String>>()
     });
     Ok(data)
 }
 
+/// Record the root of a finished bundle, only the latest `--keep-roots` bundle roots are retained.
+/// The roots and records written before it are dropped from the working set.
+async fn commit_root(
+    state: Data<AppState>,
+    Params(request): Params<RootRequest>,
+) -> Result<(), Error> {
+    state.history.lock().unwrap().commit(request.root)?;
+    state.working.lock().unwrap().commit(request.root);
+    Ok(())
+}
+
+/// The committed and pinned roots, the roots written since and the records written for them
+fn live_set(state: &AppState) -> Result<(Vec<[u8; 32]>, Vec<[u8; 32]>), ServiceError> {
+    let mut roots = state.history.lock().unwrap().live_roots()?;
+    let working = state.working.lock().unwrap();
+    roots.extend(working.roots());
+    roots.sort();
+    roots.dedup();
+    Ok((roots, working.records()))
+}
+
+/// Protect a root (for example one referenced by a pending proof) from garbage collection.
+/// Returns the number of pins on the root.
+async fn pin_root(
+    state: Data<AppState>,
+    Params(request): Params<RootRequest>,
+) -> Result<usize, Error> {
+    Ok(state.history.lock().unwrap().pin(request.root)?)
+}
+
+async fn unpin_root(
+    state: Data<AppState>,
+    Params(request): Params<RootRequest>,
+) -> Result<usize, Error> {
+    Ok(state.history.lock().unwrap().unpin(request.root)?)
+}
+
+async fn list_roots(state: Data<AppState>) -> Result<RootList, Error> {
+    let history = state.history.lock().unwrap();
+    Ok(RootList {
+        recent: history.recent.iter().cloned().collect(),
+        pinned: history
+            .pinned
+            .iter()
+            .map(|(k, c)| (hex::decode(k).unwrap().try_into().unwrap(), *c))
+            .collect(),
+    })
+}
+
+async fn gc_report(state: Data<AppState>) -> Result<GcReport, Error> {
+    let report = actix_web::web::block(move || {
+        let (roots, records) = live_set(&state)?;
+        gc::report(&state.db, &state.uri, &roots, &records)
+    })
+    .await
+    .map_err(blocking_error)??;
+    Ok(report)
+}
+
+/// Online garbage collection, all other requests are blocked until the collection finishes.
+/// Besides the committed and pinned roots it keeps the roots written since the latest commit.
+async fn collect_garbage(state: Data<AppState>) -> Result<GcReport, Error> {
+    let start = Instant::now();
+    let report = actix_web::web::block(move || {
+        let _updates = state.updates.write().unwrap();
+        let (roots, records) = live_set(&state)?;
+        state
+            .db
+            .exclusive(|db| gc::collect(db, &state.uri, &roots, &records))
+    })
+    .await
+    .map_err(blocking_error)??;
+    let duration = start.elapsed();
+    println!("time taken for gc is {:?}: {:?}", duration, report);
+    Ok(report)
+}
+
+/// Write every non-empty leaf and data record reachable from `root` into a snapshot file of the
+/// snapshot directory, the method is disabled when the service has no `--snapshot-dir`
+async fn export_snapshot(
+    state: Data<AppState>,
+    Params(request): Params<ExportSnapshotRequest>,
+) -> Result<SnapshotSummary, Error> {
+    let start = Instant::now();
+    let summary = actix_web::web::block(move || {
+        let dir = state.snapshot_dir.as_ref().ok_or_else(|| {
+            ServiceError::InvalidParams("snapshots are disabled, see --snapshot-dir".to_string())
+        })?;
+        let path = snapshot::snapshot_path(dir, &request.path)?;
+        let file = std::fs::File::create(&path)
+            .map_err(|e| ServiceError::InvalidParams(format!("{}: {:?}", request.path, e)))?;
+        snapshot::export(&state.db, request.root, &mut std::io::BufWriter::new(file))
+    })
+    .await
+    .map_err(blocking_error)??;
+    let duration = start.elapsed();
+    println!("time taken for export_snapshot is {:?}", duration);
+    Ok(summary)
+}
+
 use clap::Parser;
+use clap::Subcommand;
 
 #[derive(Parser, Debug)]
 #[clap(version = "1.0", author = "Sinka")]
@@ -131,31 +445,196 @@ struct Args {
     /// The URI to be processed
     #[clap(short, long)]
     uri: String,
+
+    /// Address the RPC server binds to
+    #[clap(long, env = "CSM_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Port the RPC server listens on
+    #[clap(short, long, env = "CSM_PORT", default_value = "3030")]
+    port: u16,
+
+    /// Number of http workers, defaults to the number of physical cores
+    #[clap(short, long, env = "CSM_WORKERS")]
+    workers: Option<usize>,
+
+    /// Serve the RPC on a unix domain socket instead of a TCP port (host and port are ignored)
+    #[clap(long, env = "CSM_UNIX_SOCKET")]
+    unix_socket: Option<String>,
+
+    /// Number of committed bundle roots kept alive by garbage collection
+    #[clap(long, env = "CSM_KEEP_ROOTS", default_value = "16")]
+    keep_roots: usize,
+
+    /// Where the root history is persisted, defaults to <uri>.roots.json
+    #[clap(long, env = "CSM_ROOT_HISTORY")]
+    root_history: Option<String>,
+
+    /// Directory the export_snapshot method writes into, the method is disabled without it
+    #[clap(long, env = "CSM_SNAPSHOT_DIR")]
+    snapshot_dir: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Remove every record that is not reachable from a committed or pinned root, the service must
+    /// not be running. Roots written after the latest commit are not known offline and are lost.
+    Gc {
+        /// Only report the reclaimable space
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Write the state reachable from a root into a snapshot file
+    ExportSnapshot {
+        /// Hex encoded root
+        #[clap(long)]
+        root: String,
+        #[clap(long)]
+        file: String,
+    },
+    /// Rebuild the state of a snapshot file and check its root
+    ImportSnapshot {
+        #[clap(long)]
+        file: String,
+    },
+}
+
+fn run_command(
+    command: Command,
+    db: SharedDB,
+    uri: &Path,
+    history: &mut RootHistory,
+) -> Result<(), ServiceError> {
+    match command {
+        Command::Gc { dry_run } => run_gc(db, uri, history, dry_run),
+        Command::ExportSnapshot { root, file } => {
+            let root: [u8; 32] = hex::decode(&root)
+                .ok()
+                .and_then(|r| r.try_into().ok())
+                .ok_or_else(|| ServiceError::InvalidParams(format!("invalid root {}", root)))?;
+            let file = std::fs::File::create(&file)
+                .map_err(|e| ServiceError::InvalidParams(format!("{}: {:?}", file, e)))?;
+            let summary = snapshot::export(&db, root, &mut std::io::BufWriter::new(file))?;
+            println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+            Ok(())
+        }
+        Command::ImportSnapshot { file } => {
+            let file = std::fs::File::open(&file)
+                .map_err(|e| ServiceError::InvalidParams(format!("{}: {:?}", file, e)))?;
+            // stage the import next to the database
+            let staging = match uri.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let summary = snapshot::import(&db, staging, std::io::BufReader::new(file))?;
+            // keep the imported state alive through garbage collection
+            history.commit(summary.root)?;
+            println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+            Ok(())
+        }
+    }
+}
+
+fn run_gc(
+    db: SharedDB,
+    uri: &Path,
+    history: &RootHistory,
+    dry_run: bool,
+) -> Result<(), ServiceError> {
+    let roots = history.live_roots()?;
+    let report = if dry_run {
+        gc::report(&db, uri, &roots, &[])?
+    } else {
+        db.exclusive(|db| gc::collect(db, uri, &roots, &[]))?
+    };
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    Ok(())
+}
+
+/// Remove the socket left at `path` by a previous run. Anything else at `path` is left alone and
+/// reported, binding would fail on it anyway.
+fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
 }
 
 fn main() -> std::io::Result<()> {
-    let args = Args::parse();
-    unsafe { DB = Some(Rc::new(RefCell::new(RocksDB::new(args.uri).unwrap()))) };
+    let mut args = Args::parse();
+    let uri = PathBuf::from(&args.uri);
+    let history_path = args.root_history.clone().map_or(
+        PathBuf::from(format!("{}.roots.json", args.uri)),
+        PathBuf::from,
+    );
+    let mut history = RootHistory::load(&history_path, args.keep_roots)?;
+    gc::recover(&uri).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.message()))?;
+    let db = SharedDB::new(RocksDB::new(args.uri.clone()).unwrap());
+
+    if let Some(command) = args.command.take() {
+        return run_command(command, db, &uri, &mut history)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.message()));
+    }
+
     let rpc = Server::new()
-        .with_data(Data::new(String::from("Hello!")))
+        .with_data(Data::new(AppState {
+            db,
+            uri,
+            history: Mutex::new(history),
+            working: Mutex::new(WorkingSet::new(args.keep_roots)),
+            snapshot_dir: args.snapshot_dir.clone().map(PathBuf::from),
+            updates: RwLock::new(()),
+        }))
         .with_method("update_leaf", update_leaf)
         .with_method("get_leaf", get_leaf)
+        .with_method("get_leaf_proof", get_leaf_proof)
+        .with_method("verify_leaf_proof", verify_leaf_proof)
+        .with_method("update_leaves", update_leaves)
+        .with_method("get_leaves", get_leaves)
         .with_method("update_record", update_record)
         .with_method("get_record", get_record)
+        .with_method("commit_root", commit_root)
+        .with_method("pin_root", pin_root)
+        .with_method("unpin_root", unpin_root)
+        .with_method("list_roots", list_roots)
+        .with_method("gc_report", gc_report)
+        .with_method("gc", collect_garbage)
+        .with_method("export_snapshot", export_snapshot)
         .finish();
 
-    actix_web::rt::System::new().block_on(
-        actix_web::HttpServer::new(move || {
+    actix_web::rt::System::new().block_on(async move {
+        let mut server = actix_web::HttpServer::new(move || {
             let rpc = rpc.clone();
             actix_web::App::new().service(
                 actix_web::web::service("/")
                     .guard(actix_web::guard::Post())
                     .finish(rpc.into_web_service()),
             )
-        })
-        .bind("0.0.0.0:3030")?
-        .run(),
-    )
+        });
+        if let Some(workers) = args.workers {
+            server = server.workers(workers);
+        }
+        let server = match args.unix_socket {
+            Some(path) => {
+                remove_stale_socket(Path::new(&path))?;
+                println!("csm_service listening on unix socket {}", path);
+                server.bind_uds(path)?
+            }
+            None => {
+                println!("csm_service listening on {}:{}", args.host, args.port);
+                server.bind((args.host.as_str(), args.port))?
+            }
+        };
+        server.run().await
+    })
 }
 
 #[test]
@@ -182,3 +661,44 @@ fn update_leaf_test() {
         .unwrap();
     println!("update leaf done");
 }
+
+#[test]
+fn parse_leaf_index_test() {
+    assert_eq!(parse_leaf_index("4294967295").unwrap(), 4294967295);
+    assert_eq!(parse_leaf_index("8589934590").unwrap(), 8589934590);
+    let too_small = parse_leaf_index("4294967294").unwrap_err();
+    assert_eq!(too_small.code(), error::INVALID_PARAMS);
+    let too_large = parse_leaf_index("8589934591").unwrap_err();
+    assert_eq!(too_large.code(), error::INVALID_PARAMS);
+    let malformed = parse_leaf_index("0x10").unwrap_err();
+    assert_eq!(malformed.code(), error::INVALID_PARAMS);
+}
+
+#[test]
+fn get_known_mt_test() {
+    let closed = SharedDB::new(db::ClosedDB);
+    let storage = get_known_mt(&closed, [1; 32]).err().unwrap();
+    assert_eq!(storage.code(), error::STORAGE_FAILURE);
+    assert!(get_known_mt(&closed, *EMPTY_ROOT).is_ok());
+
+    let dir = tempfile::tempdir().unwrap();
+    let db = SharedDB::new(gc::open_rocksdb(dir.path()).unwrap());
+    let unknown = get_known_mt(&db, [1; 32]).err().unwrap();
+    assert_eq!(unknown.code(), error::UNKNOWN_ROOT);
+}
+
+#[test]
+fn remove_stale_socket_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("csm.sock");
+    let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+    drop(listener);
+    remove_stale_socket(&socket).unwrap();
+    assert!(!socket.exists());
+    remove_stale_socket(&socket).unwrap();
+
+    let file = dir.path().join("merkledb");
+    std::fs::write(&file, b"data").unwrap();
+    assert!(remove_stale_socket(&file).is_err());
+    assert!(file.exists());
+}
@@ -0,0 +1,264 @@
+use std::io::{BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use zkwasm_host_circuits::host::datahash::DataHashRecord;
+use zkwasm_host_circuits::host::db::TreeDB;
+use zkwasm_host_circuits::host::merkle::MerkleTree;
+use zkwasm_host_circuits::host::mongomerkle::MongoMerkle;
+
+use crate::db::SharedDB;
+use crate::error::{parse_leaf_index, ServiceError};
+use crate::gc::open_rocksdb;
+use crate::walk::{default_hashes, Visit, Walker};
+use crate::MERKLE_DEPTH;
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A snapshot is a json-lines file: a header followed by every non-empty leaf reachable from the
+/// root in index order and every data record referenced by those leaves.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotEntry {
+    Header {
+        version: u32,
+        depth: usize,
+        root: [u8; 32],
+    },
+    Leaf {
+        index: String, // u64 encoding
+        data: [u8; 32],
+    },
+    Record {
+        hash: [u8; 32],
+        data: String, // hex encoding
+    },
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SnapshotSummary {
+    pub root: [u8; 32],
+    pub leaves: usize,
+    pub records: usize,
+}
+
+fn io_error<E: std::fmt::Debug>(e: E) -> ServiceError {
+    ServiceError::Storage(format!("{:?}", e))
+}
+
+/// Resolve the snapshot file `name` inside `dir`, only plain relative paths are accepted so that
+/// clients can not write outside of `dir`
+pub fn snapshot_path(dir: &Path, name: &str) -> Result<PathBuf, ServiceError> {
+    let path = Path::new(name);
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(ServiceError::InvalidParams(format!(
+            "snapshot path {:?} must be relative to the snapshot directory",
+            name
+        )));
+    }
+    Ok(dir.join(path))
+}
+
+fn write_entry<W: Write>(writer: &mut W, entry: &SnapshotEntry) -> Result<(), ServiceError> {
+    serde_json::to_writer(&mut *writer, entry).map_err(io_error)?;
+    writer.write_all(b"\n").map_err(io_error)
+}
+
+pub fn export<W: Write>(
+    db: &dyn TreeDB,
+    root: [u8; 32],
+    writer: &mut W,
+) -> Result<SnapshotSummary, ServiceError> {
+    let mut summary = SnapshotSummary {
+        root,
+        ..SnapshotSummary::default()
+    };
+    write_entry(
+        writer,
+        &SnapshotEntry::Header {
+            version: SNAPSHOT_VERSION,
+            depth: MERKLE_DEPTH,
+            root,
+        },
+    )?;
+    let mut walker = Walker::new(db, false);
+    walker.walk(root, &mut |v| match v {
+        Visit::Leaf { index, data } => {
+            summary.leaves += 1;
+            write_entry(
+                writer,
+                &SnapshotEntry::Leaf {
+                    index: index.to_string(),
+                    data,
+                },
+            )
+        }
+        Visit::Data(r) => {
+            summary.records += 1;
+            write_entry(
+                writer,
+                &SnapshotEntry::Record {
+                    hash: r.hash,
+                    data: hex::encode(&r.data),
+                },
+            )
+        }
+        Visit::Node(_) => Ok(()),
+    })?;
+    writer.flush().map_err(io_error)?;
+    Ok(summary)
+}
+
+/// Rebuild the tree of a snapshot starting from the empty tree and check that the recomputed root
+/// matches the root recorded in the header. The tree is built in a temporary database created in
+/// `staging` and only copied into `db` once its root is checked, so a corrupted snapshot leaves
+/// `db` untouched.
+pub fn import<R: BufRead>(db: &SharedDB, staging: &Path, reader: R) -> Result<SnapshotSummary, ServiceError> {
+    let mut lines = reader.lines();
+    let header = lines
+        .next()
+        .ok_or_else(|| ServiceError::InvalidParams("empty snapshot".to_string()))?
+        .map_err(io_error)?;
+    let expected = match serde_json::from_str::<SnapshotEntry>(&header) {
+        Ok(SnapshotEntry::Header {
+            version,
+            depth,
+            root,
+        }) if version == SNAPSHOT_VERSION && depth == MERKLE_DEPTH => root,
+        _ => {
+            return Err(ServiceError::InvalidParams(format!(
+                "unsupported snapshot header {}",
+                header
+            )))
+        }
+    };
+
+    let dir = tempfile::Builder::new()
+        .prefix("snapshot")
+        .tempdir_in(staging)
+        .map_err(io_error)?;
+    let stage = SharedDB::new(open_rocksdb(dir.path())?);
+    let mut summary = SnapshotSummary::default();
+    let mut mt = MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], default_hashes()[0], stage.tree_db());
+    let mut records = stage.clone();
+    for line in lines {
+        let line = line.map_err(io_error)?;
+        let entry = serde_json::from_str::<SnapshotEntry>(&line)
+            .map_err(|e| ServiceError::InvalidParams(format!("{:?}", e)))?;
+        match entry {
+            SnapshotEntry::Leaf { index, data } => {
+                let index = parse_leaf_index(index.as_str())?;
+                mt.update_leaf_data_with_proof(index, &data.to_vec())
+                    .map_err(|e| ServiceError::Storage(format!("{:?}", e)))?;
+                summary.leaves += 1;
+            }
+            SnapshotEntry::Record { hash, data } => {
+                let data = hex::decode(data)
+                    .map_err(|e| ServiceError::InvalidParams(format!("{:?}", e)))?;
+                records
+                    .set_data_record(DataHashRecord { hash, data })
+                    .map_err(io_error)?;
+                summary.records += 1;
+            }
+            SnapshotEntry::Header { .. } => {
+                return Err(ServiceError::InvalidParams(
+                    "unexpected snapshot header".to_string(),
+                ))
+            }
+        }
+    }
+    summary.root = mt.get_root_hash();
+    if summary.root != expected {
+        return Err(ServiceError::InvalidParams(format!(
+            "snapshot root mismatch: expect {} but rebuilt {}",
+            hex::encode(expected),
+            hex::encode(summary.root)
+        )));
+    }
+
+    let mut target = db.clone();
+    let mut nodes = vec![];
+    Walker::new(&stage, true).walk(summary.root, &mut |v| match v {
+        Visit::Node(r) => {
+            nodes.push(r.clone());
+            Ok(())
+        }
+        Visit::Data(r) => target.set_data_record(r.clone()).map_err(io_error),
+        Visit::Leaf { .. } => Ok(()),
+    })?;
+    target.set_merkle_records(&nodes).map_err(io_error)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use zkwasm_host_circuits::host::datahash::{DataHashRecord, MongoDataHash};
+    use zkwasm_host_circuits::host::db::TreeDB;
+    use zkwasm_host_circuits::host::merkle::MerkleTree;
+    use zkwasm_host_circuits::host::mongomerkle::MongoMerkle;
+
+    use super::{export, import, snapshot_path, SnapshotEntry};
+    use crate::db::SharedDB;
+    use crate::gc::open_rocksdb;
+    use crate::walk::default_hashes;
+    use crate::MERKLE_DEPTH;
+
+    const LEAF: u64 = (1u64 << MERKLE_DEPTH) - 1;
+
+    fn leaf(db: &SharedDB, root: [u8; 32], index: u64) -> [u8; 32] {
+        let mt = MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], root, db.tree_db());
+        mt.get_leaf_with_proof(index).unwrap().0.data.unwrap_or([0; 32])
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = SharedDB::new(open_rocksdb(&dir.path().join("source")).unwrap());
+        let mut mt = MongoMerkle::<MERKLE_DEPTH>::construct([0; 32], default_hashes()[0], source.tree_db());
+        mt.update_leaf_data_with_proof(LEAF, &[1; 32].to_vec()).unwrap();
+        mt.update_leaf_data_with_proof(LEAF + 9, &[2; 32].to_vec()).unwrap();
+        let root = mt.get_root_hash();
+        let mut datahash = MongoDataHash::construct([0; 32], source.tree_db());
+        datahash
+            .update_record(DataHashRecord {
+                hash: [1; 32],
+                data: vec![3; 24],
+            })
+            .unwrap();
+
+        let mut bytes = vec![];
+        let exported = export(&source, root, &mut bytes).unwrap();
+        assert_eq!((exported.leaves, exported.records), (2, 1));
+
+        let target = SharedDB::new(open_rocksdb(&dir.path().join("target")).unwrap());
+        let imported = import(&target, dir.path(), bytes.as_slice()).unwrap();
+        assert_eq!(imported.root, root);
+        assert_eq!((imported.leaves, imported.records), (2, 1));
+        assert_eq!(leaf(&target, root, LEAF), [1; 32]);
+        assert_eq!(leaf(&target, root, LEAF + 9), [2; 32]);
+        assert_eq!(target.get_data_record(&[1; 32]).unwrap().unwrap().data, vec![3; 24]);
+
+        // a snapshot whose header does not match its leaves leaves the target untouched
+        let mut lines = String::from_utf8(bytes).unwrap().lines().map(String::from).collect::<Vec<_>>();
+        lines[0] = serde_json::to_string(&SnapshotEntry::Header {
+            version: super::SNAPSHOT_VERSION,
+            depth: MERKLE_DEPTH,
+            root: [7; 32],
+        })
+        .unwrap();
+        let other = SharedDB::new(open_rocksdb(&dir.path().join("other")).unwrap());
+        assert!(import(&other, dir.path(), lines.join("\n").as_bytes()).is_err());
+        assert!(other.get_merkle_record(&root).unwrap().is_none());
+        assert!(other.get_data_record(&[1; 32]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_path() {
+        let dir = std::path::Path::new("/snapshots");
+        assert_eq!(snapshot_path(dir, "a/b.jsonl").unwrap(), dir.join("a/b.jsonl"));
+        assert!(snapshot_path(dir, "/etc/passwd").is_err());
+        assert!(snapshot_path(dir, "../b.jsonl").is_err());
+        assert!(snapshot_path(dir, "a/../../b.jsonl").is_err());
+        assert!(snapshot_path(dir, "").is_err());
+    }
+}
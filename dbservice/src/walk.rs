@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use zkwasm_host_circuits::host::datahash::DataHashRecord;
+use zkwasm_host_circuits::host::db::TreeDB;
+use zkwasm_host_circuits::host::merkle::MerkleNode;
+use zkwasm_host_circuits::host::merkle::MerkleTree;
+use zkwasm_host_circuits::host::mongomerkle::MerkleRecord;
+use zkwasm_host_circuits::host::mongomerkle::MongoMerkle;
+
+use crate::error::ServiceError;
+use crate::MERKLE_DEPTH;
+
+/// Hashes of the empty subtrees indexed by depth: `[0]` is the empty root and `[MERKLE_DEPTH]` the
+/// empty leaf. Empty subtrees are never stored in the database.
+pub fn default_hashes() -> Vec<[u8; 32]> {
+    let mut leaf = MerkleRecord::new(0);
+    leaf.set(&[0; 32].to_vec());
+    let mut hashes = vec![leaf.hash()];
+    for _ in 0..MERKLE_DEPTH {
+        let child = *hashes.last().unwrap();
+        hashes.push(MongoMerkle::<MERKLE_DEPTH>::hash(&child, &child));
+    }
+    hashes.reverse();
+    hashes
+}
+
+pub enum Visit<'a> {
+    /// A stored merkle node (internal node or leaf)
+    Node(&'a MerkleRecord),
+    /// A non-empty leaf with its position in the tree
+    Leaf { index: u64, data: [u8; 32] },
+    /// A data record referenced by a leaf or by another data record
+    Data(&'a DataHashRecord),
+}
+
+/// Walks everything that is reachable from a set of roots.
+///
+/// Leaves only hold 32 bytes, larger values live in data records keyed by their hash, which may in
+/// turn reference other data records. Records are written by the host as u64 words and their
+/// layout is up to the app, so the walker follows references by this rule: a data record is
+/// reachable if its hash is the data of a reachable leaf, or if its hash is stored at any offset
+/// of a reachable data record. Every 32 byte window of a record is looked up, so a reference is
+/// followed wherever the app puts it, and a window that is not a reference only keeps a record
+/// alive if it equals the hash of a stored record.
+pub struct Walker<'a> {
+    db: &'a dyn TreeDB,
+    defaults: Vec<[u8; 32]>,
+    /// Skip merkle subtrees whose hash was already visited. This is what a marker wants, but an
+    /// exporter needs every leaf position even if two subtrees share the same content.
+    dedup: bool,
+    pub merkle: HashSet<[u8; 32]>,
+    pub data: HashSet<[u8; 32]>,
+}
+
+impl<'a> Walker<'a> {
+    pub fn new(db: &'a dyn TreeDB, dedup: bool) -> Self {
+        Walker {
+            db,
+            defaults: default_hashes(),
+            dedup,
+            merkle: HashSet::new(),
+            data: HashSet::new(),
+        }
+    }
+
+    pub fn walk<F>(&mut self, root: [u8; 32], visit: &mut F) -> Result<(), ServiceError>
+    where
+        F: FnMut(Visit) -> Result<(), ServiceError>,
+    {
+        let mut stack = vec![(0u64, 0usize, root)];
+        while let Some((index, depth, hash)) = stack.pop() {
+            if hash == self.defaults[depth] {
+                continue;
+            }
+            if !self.merkle.insert(hash) && self.dedup {
+                continue;
+            }
+            let record = self
+                .db
+                .get_merkle_record(&hash)
+                .map_err(|e| ServiceError::Storage(format!("{:?}", e)))?
+                .ok_or_else(|| {
+                    if depth == 0 {
+                        ServiceError::UnknownRoot(hash)
+                    } else {
+                        ServiceError::MissingRecord(format!(
+                            "merkle node {} at index {}",
+                            hex::encode(hash),
+                            index
+                        ))
+                    }
+                })?;
+            visit(Visit::Node(&record))?;
+            if depth == MERKLE_DEPTH {
+                let data = record.data.unwrap_or([0; 32]);
+                visit(Visit::Leaf { index, data })?;
+                self.walk_data(data, visit)?;
+            } else {
+                // push the right child first so that leaves are visited in index order
+                if let Some(right) = record.right {
+                    stack.push((index * 2 + 2, depth + 1, right));
+                }
+                if let Some(left) = record.left {
+                    stack.push((index * 2 + 1, depth + 1, left));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the data record `hash` and the records it references, a missing record is skipped
+    pub fn walk_data<F>(&mut self, hash: [u8; 32], visit: &mut F) -> Result<(), ServiceError>
+    where
+        F: FnMut(Visit) -> Result<(), ServiceError>,
+    {
+        let mut stack = vec![hash];
+        while let Some(hash) = stack.pop() {
+            if hash == [0; 32] || self.data.contains(&hash) {
+                continue;
+            }
+            let record = self
+                .db
+                .get_data_record(&hash)
+                .map_err(|e| ServiceError::Storage(format!("{:?}", e)))?;
+            if let Some(record) = record {
+                self.data.insert(hash);
+                visit(Visit::Data(&record))?;
+                for window in record.data.windows(32) {
+                    stack.push(window.try_into().unwrap());
+                }
+            }
+        }
+        Ok(())
+    }
+}
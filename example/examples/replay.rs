@@ -0,0 +1,5 @@
+zkwasm_rest_harness::harness_app!(App, zkwasm_settlement_test);
+
+fn main() {
+    zkwasm_rest_harness::replay::main::<App>()
+}
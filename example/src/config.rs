@@ -3,10 +3,19 @@ use serde::Serialize;
 #[derive(Serialize, Clone)]
 pub struct Config {
     version: &'static str,
+    admins: Vec<[u64; 2]>,
 }
+
+/// Player ids of the genesis admins, the pid of the admin public key `pkey` is `[pkey[1], pkey[2]]`
+pub const ADMINS: [[u64; 2]; 1] = [[6, 7]];
+
+/// Id of this deployment mixed into every signed transaction
+pub const SIGNATURE_DOMAIN: u64 = 0x68656c6c6f;
+
 lazy_static::lazy_static! {
     pub static ref CONFIG: Config = Config {
-        version: "1.0"
+        version: "1.0",
+        admins: ADMINS.to_vec(),
     };
 }
 
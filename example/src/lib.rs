@@ -4,6 +4,16 @@ pub mod config;
 pub mod state;
 
 use crate::config::Config;
-use crate::state::{State, Transaction};
+use crate::state::{Error, State, Transaction};
 use zkwasm_rest_convention::CommonState;
-zkwasm_rest_abi::create_zkwasm_apis!(Transaction, State, Config);
+zkwasm_rest_abi::create_zkwasm_apis!(Transaction, State, Config, Error;
+    outputs = &[
+        PublicOutput::Root,
+        PublicOutput::Settlement,
+        PublicOutput::PreRoot,
+        PublicOutput::BundleCounter,
+    ],
+    settlement_format = SettlementFormat::Tagged,
+    replay_guard = true,
+    domain = config::SIGNATURE_DOMAIN,
+);
@@ -1,38 +1,92 @@
+use crate::config::ADMINS;
 use crate::StorageData;
 use crate::MERKLE_MAP;
 use core::slice::IterMut;
-use serde::Serialize;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 use std::cell::{Ref, RefCell, RefMut};
-use zkwasm_rest_abi::Player;
+use zkwasm_rest_abi::{Player, TxResult};
+use zkwasm_rest_convention::admin::AdminSet;
+use zkwasm_rest_convention::event::{EventHandler, EventQueue};
+use zkwasm_rest_convention::player::{
+    Command, CommandHandler, SubCommand, TransactionData, WithBalance, COMMAND_BASE,
+    ERROR_INSUFFICIENT_BALANCE, ERROR_LAST_ADMIN, ERROR_NOT_ADMIN, ERROR_PLAYER_ALREADY_EXIST,
+    ERROR_PLAYER_NOT_EXIST, ERROR_UNSUPPORTED_TOKEN,
+};
 use zkwasm_rest_convention::CommonState;
-use zkwasm_rest_convention::SettlementInfo;
+use zkwasm_rest_convention::settlement::{SettlementInfo, SettlementMessage};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Default, StorageData)]
 pub struct PlayerData {
     pub counter: u64,
+    pub balance: u64,
 }
 
-impl Default for PlayerData {
-    fn default() -> Self {
-        Self { counter: 0 }
+impl WithBalance for PlayerData {
+    fn cost_balance(&mut self, amount: u64) -> Result<(), u32> {
+        if self.balance < amount {
+            Err(ERROR_INSUFFICIENT_BALANCE)
+        } else {
+            self.balance -= amount;
+            Ok(())
+        }
+    }
+    fn inc_balance(&mut self, amount: u64) {
+        self.balance += amount;
     }
 }
 
-impl StorageData for PlayerData {
+pub type HelloWorldPlayer = Player<PlayerData>;
+
+/// Increase the counter of `owner` once the event is triggered by the ticker
+#[derive(Clone, Debug, PartialEq)]
+pub struct CounterEvent {
+    pub delta: usize,
+    pub owner: [u64; 2],
+}
+
+impl StorageData for CounterEvent {
     fn from_data(u64data: &mut IterMut<u64>) -> Self {
-        let counter = *u64data.next().unwrap();
-        PlayerData { counter }
+        let delta = *u64data.next().unwrap() as usize;
+        let owner = [*u64data.next().unwrap(), *u64data.next().unwrap()];
+        CounterEvent { delta, owner }
     }
     fn to_data(&self, data: &mut Vec<u64>) {
-        data.push(self.counter);
+        data.push(self.delta as u64);
+        data.push(self.owner[0]);
+        data.push(self.owner[1]);
     }
 }
 
-pub type HelloWorldPlayer = Player<PlayerData>;
+impl EventHandler for CounterEvent {
+    fn get_delta(&self) -> usize {
+        self.delta
+    }
+    fn progress(&mut self, d: usize) {
+        self.delta -= d;
+    }
+    fn handle(&mut self, _counter: u64) -> Option<Self> {
+        if let Some(mut player) = HelloWorldPlayer::get_from_pid(&self.owner) {
+            player.data.counter += 1;
+            player.store();
+        }
+        None
+    }
+    fn u64size() -> usize {
+        3
+    }
+}
 
-#[derive(Serialize, Default)]
 pub struct State {
-    tick: u64
+    queue: EventQueue<CounterEvent>,
+}
+
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("State", 1)?;
+        state.serialize_field("tick", &self.queue.counter)?;
+        state.end()
+    }
 }
 
 pub struct SafeState(pub RefCell<State>);
@@ -51,95 +105,235 @@ impl CommonState for State {
     fn get_global_mut<'a>() -> RefMut<'a, State> {
         GLOBAL_STATE.0.borrow_mut()
     }
+    fn genesis_admins() -> Vec<[u64; 2]> {
+        ADMINS.to_vec()
+    }
 }
 
 impl StorageData for State {
     fn from_data(u64data: &mut IterMut<u64>) -> Self {
         State {
-            tick: *u64data.next().unwrap()
+            queue: EventQueue::from_data(u64data),
         }
     }
     fn to_data(&self, data: &mut Vec<u64>) {
-        data.push(self.tick);
+        self.queue.to_data(data);
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl State {
-    pub fn flush_settlement() -> Vec<u8> {
-        let data = SettlementInfo::flush_settlement();
-        data
+    pub fn flush_settlement() -> Vec<SettlementMessage> {
+        SettlementInfo::flush_messages()
     }
     pub fn new() -> Self {
         State {
-            tick: 0
+            queue: EventQueue::new(),
         }
     }
     pub fn preempt() -> bool {
-        return Self::get_global().tick % 5 == 0
+        Self::get_global().queue.counter % 5 == 0
     }
 
     pub fn store() {
-        unsafe { Self::get_global().store() };
+        let mut state = Self::get_global_mut();
+        state.queue.store();
+        state.store();
+    }
+
+    pub fn tick() {
+        Self::get_global_mut().queue.tick();
     }
 }
 
-pub struct Transaction {
-    pub command: u64,
-    pub data: Vec<u64>,
+/* 4 increase the counter of the player
+ * 5 increase the counter of the player after a number of ticks
+ */
+const INC_COUNTER: u64 = COMMAND_BASE;
+const DELAYED_INC_COUNTER: u64 = COMMAND_BASE + 1;
+
+/// Longest delay that a delayed increase can be scheduled with
+const MAX_DELAY: u64 = 1024;
+
+zkwasm_rest_abi::tx_errors! {
+    pub enum Error {
+        PlayerAlreadyExist = ERROR_PLAYER_ALREADY_EXIST,
+        PlayerNotExist = ERROR_PLAYER_NOT_EXIST,
+        InsufficientBalance = ERROR_INSUFFICIENT_BALANCE,
+        UnsupportedToken = ERROR_UNSUPPORTED_TOKEN,
+        NotAdmin = ERROR_NOT_ADMIN,
+        LastAdmin = ERROR_LAST_ADMIN,
+        InvalidDelay = 16,
+    }
 }
 
-const TICK: u64 = 0;
-const INSTALL_PLAYER: u64 = 1;
-const INC_COUNTER: u64 = 2;
+const ERROR_INVALID_DELAY: u32 = Error::InvalidDelay as u32;
 
-const ERROR_PLAYER_ALREADY_EXIST: u32 = 1;
-const ERROR_PLAYER_NOT_EXIST: u32 = 2;
+#[derive(Clone, Debug, PartialEq)]
+pub enum Activity {
+    IncCounter,
+    DelayedIncCounter(u64),
+}
 
-impl Transaction {
-    pub fn decode_error(e: u32) -> &'static str {
-        match e {
-            ERROR_PLAYER_NOT_EXIST => "PlayerNotExist",
-            ERROR_PLAYER_ALREADY_EXIST => "PlayerAlreadyExist",
-            _ => "Unknown",
+impl SubCommand for Activity {
+    fn decode(command: u64, params: &[u64]) -> Option<Self> {
+        match command {
+            INC_COUNTER => Some(Activity::IncCounter),
+            DELAYED_INC_COUNTER => Some(Activity::DelayedIncCounter(params[0])),
+            _ => None,
+        }
+    }
+}
+
+impl Activity {
+    pub fn handle(&self, pid: &[u64; 2], nonce: u64) -> Result<(), u32> {
+        let mut player = HelloWorldPlayer::get_from_pid(pid).ok_or(ERROR_PLAYER_NOT_EXIST)?;
+        player.check_and_inc_nonce(nonce);
+        match self {
+            Activity::IncCounter => {
+                player.data.counter += 1;
+            }
+            Activity::DelayedIncCounter(delay) => {
+                if *delay > MAX_DELAY {
+                    return Err(ERROR_INVALID_DELAY);
+                }
+                State::get_global_mut().queue.insert(CounterEvent {
+                    delta: *delay as usize,
+                    owner: *pid,
+                });
+            }
         }
+        player.store();
+        Ok(())
     }
+}
+
+pub struct Transaction {
+    pub data: TransactionData<Activity>,
+}
+
+impl Transaction {
     pub fn decode(params: &[u64]) -> Self {
-        let command = params[0] & 0xff;
-        //let nonce = params[0] >> 16;
-        let data = vec![params[1], params[2], params[3]]; // pkey[0], pkey[1], amount
-        Transaction { command, data }
+        Transaction {
+            data: TransactionData::decode_with(params, &crate::API_OPTIONS),
+        }
     }
-    pub fn install_player(&self, pkey: &[u64; 4]) -> u32 {
+
+    pub fn install_player(&self, pid: &[u64; 2]) -> Result<(), u32> {
         zkwasm_rust_sdk::dbg!("install \n");
-        let pid = HelloWorldPlayer::pkey_to_pid(pkey);
-        let player = HelloWorldPlayer::get_from_pid(&pid);
-        match player {
-            Some(_) => ERROR_PLAYER_ALREADY_EXIST,
+        match HelloWorldPlayer::get_from_pid(pid) {
+            Some(_) => Err(ERROR_PLAYER_ALREADY_EXIST),
             None => {
-                let player = HelloWorldPlayer::new_from_pid(pid);
+                let mut player = HelloWorldPlayer::new_from_pid(*pid);
+                player.check_and_inc_nonce(self.data.nonce);
                 player.store();
-                0
+                Ok(())
             }
         }
     }
 
-    pub fn inc_counter(&self, _pkey: &[u64; 4]) -> u32 {
-        //let player = HelloWorldPlayer::get(pkey);
-        todo!()
-    }
-
-    pub fn process(&self, pkey: &[u64; 4], _rand: &[u64; 4]) -> Vec<u64> {
-        let b = match self.command {
-            TICK => {
-                State::get_global_mut().tick += 1;
-                0
-            },
-            INSTALL_PLAYER => self.install_player(pkey),
-            INC_COUNTER => self.inc_counter(pkey),
-            _ => 0,
+    pub fn process(&self, pkey: &[u64; 4], rand: &[u64; 4]) -> TxResult {
+        let pid = HelloWorldPlayer::pkey_to_pid(pkey);
+        let nonce = self.data.nonce;
+        let counter = State::get_global().queue.counter;
+        let b = match &self.data.command {
+            // only an admin key runs the ticker
+            Command::Tick => AdminSet::check_admin(&pid).map(|_| State::tick()),
+            Command::InstallPlayer => self.install_player(&pid),
+            Command::Withdraw(withdraw) => withdraw.handle::<PlayerData>(&pid, nonce, rand, counter),
+            Command::Deposit(deposit) => deposit.handle::<PlayerData>(&pid, nonce, rand, counter),
+            Command::RotateAdmin(rotate) => rotate.handle::<PlayerData>(&pid, nonce, rand, counter),
+            Command::Activity(activity) => activity.handle(&pid, nonce),
         };
         let kvpair = unsafe { &mut MERKLE_MAP.merkle.root };
         zkwasm_rust_sdk::dbg!("root after process {:?}\n", kvpair);
-        vec![b as u64]
+        b.map_or_else(TxResult::error, |_| TxResult::success(vec![]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_commands() {
+        let install = Transaction::decode(&[1 | (1 << 8), 0, 0, 0, 0]);
+        assert!(matches!(install.data.command, Command::InstallPlayer));
+        assert_eq!(install.data.nonce, 0);
+
+        let inc = Transaction::decode(&[INC_COUNTER | (1 << 8) | (3 << 16), 0, 0, 0, 0]);
+        assert!(matches!(inc.data.command, Command::Activity(Activity::IncCounter)));
+        assert_eq!(inc.data.nonce, 3);
+
+        let delayed = Transaction::decode(&[DELAYED_INC_COUNTER | (2 << 8) | (4 << 16), 7, 0, 0, 0]);
+        assert!(matches!(
+            delayed.data.command,
+            Command::Activity(Activity::DelayedIncCounter(7))
+        ));
+
+        let withdraw = Transaction::decode(&[2 | (5 << 8), 1, (9 << 32) | 300, 0, 0]);
+        match withdraw.data.command {
+            Command::Withdraw(w) => assert_eq!((w.token, w.amount), (1, 300)),
+            _ => panic!("expected withdraw"),
+        }
+        let wide = Transaction::decode(&[2 | (6 << 8), 0, 9 << 32, 0, 0, 1 << 40]);
+        match wide.data.command {
+            Command::Withdraw(w) => assert_eq!((w.token, w.amount), (0, 1 << 40)),
+            _ => panic!("expected withdraw"),
+        }
+    }
+
+    #[test]
+    fn test_storage_roundtrip() {
+        let player = PlayerData {
+            counter: 3,
+            balance: 100,
+        };
+        let mut data = vec![];
+        player.to_data(&mut data);
+        let decoded = PlayerData::from_data(&mut data.iter_mut());
+        assert_eq!((decoded.counter, decoded.balance), (3, 100));
+
+        let event = CounterEvent {
+            delta: 5,
+            owner: [1, 2],
+        };
+        let mut data = vec![];
+        event.to_data(&mut data);
+        assert_eq!(data.len(), CounterEvent::u64size());
+        assert_eq!(CounterEvent::from_data(&mut data.iter_mut()), event);
+    }
+
+    #[test]
+    fn test_decode_error() {
+        use zkwasm_rest_abi::TxError;
+        assert_eq!(Error::decode_error(ERROR_PLAYER_NOT_EXIST), "PlayerNotExist");
+        assert_eq!(Error::decode_error(ERROR_INVALID_DELAY), "InvalidDelay");
+        assert_eq!(Error::decode_error(0), "Unknown");
+    }
+
+    #[test]
+    fn test_balance() {
+        let mut player = PlayerData::default();
+        player.inc_balance(10);
+        assert_eq!(player.cost_balance(11), Err(ERROR_INSUFFICIENT_BALANCE));
+        assert_eq!(player.cost_balance(4), Ok(()));
+        assert_eq!(player.balance, 6);
+    }
+
+    #[test]
+    fn test_event_queue_order() {
+        let mut queue = EventQueue::<CounterEvent>::new();
+        queue.insert(CounterEvent { delta: 5, owner: [1, 0] });
+        queue.insert(CounterEvent { delta: 2, owner: [2, 0] });
+        queue.insert(CounterEvent { delta: 7, owner: [3, 0] });
+        // the queue stores the differences between consecutive events
+        let deltas = queue.list.iter().map(|e| (e.owner[0], e.delta)).collect::<Vec<_>>();
+        assert_eq!(deltas, vec![(2, 2), (1, 3), (3, 2)]);
     }
 }
@@ -0,0 +1,257 @@
+use serde_json::Value;
+use zkwasm_rest_abi::layout::BundleCounter;
+use zkwasm_rest_abi::TxResult;
+use zkwasm_rest_convention::player::{ERROR_LAST_ADMIN, ERROR_NOT_ADMIN, ERROR_UNSUPPORTED_TOKEN};
+use zkwasm_rest_harness::signer::Signer;
+use zkwasm_rest_harness::{command, harness_app, Harness, EMPTY_ROOT};
+use zkwasm_settlement_test::config::SIGNATURE_DOMAIN;
+
+harness_app!(App, zkwasm_settlement_test);
+
+const PLAYER: [u64; 4] = [1, 2, 3, 4];
+// pid [6, 7] is the genesis admin of the example, it also runs the ticker
+const ADMIN: [u64; 4] = [5, 6, 7, 8];
+
+fn code(result: Vec<u64>) -> u32 {
+    TxResult::from_slice(&result).unwrap().code
+}
+
+fn counter(harness: &Harness<App>) -> u64 {
+    let state: Value = serde_json::from_str(&harness.get_state(&PLAYER)).unwrap();
+    state["data"]["counter"].as_u64().unwrap()
+}
+
+#[test]
+fn test_counter_scenario() {
+    let harness = Harness::<App>::new();
+    let empty_root = harness.root();
+
+    assert_eq!(code(harness.send(&PLAYER, &command(1, 0, &[]))), 0);
+    assert_eq!(code(harness.send(&PLAYER, &command(1, 1, &[]))), 1);
+    assert_eq!(harness.decode_error(1), "PlayerAlreadyExist");
+
+    // the failed install consumed nonce 1
+    assert_eq!(code(harness.send(&PLAYER, &command(4, 2, &[]))), 0);
+    assert_eq!(counter(&harness), 1);
+
+    // the delayed increase is applied on the third tick
+    assert_eq!(code(harness.send(&PLAYER, &command(5, 3, &[2]))), 0);
+    for nonce in 0..2 {
+        assert_eq!(code(harness.send(&ADMIN, &command(0, nonce, &[]))), 0);
+    }
+    assert_eq!(counter(&harness), 1);
+    assert_eq!(code(harness.send(&ADMIN, &command(0, 2, &[]))), 0);
+    assert_eq!(counter(&harness), 2);
+
+    assert_eq!(BundleCounter::get(), 0);
+    assert!(harness.finalize().is_empty());
+    assert_eq!(BundleCounter::get(), 1);
+    assert_ne!(harness.root(), empty_root);
+    assert_eq!(
+        harness.snapshot(),
+        serde_json::json!({"tick": 3}).to_string()
+    );
+}
+
+#[test]
+fn test_admin_deposit() {
+    let harness = Harness::<App>::new();
+    assert_eq!(code(harness.send(&PLAYER, &command(1, 0, &[]))), 0);
+    assert_eq!(code(harness.send(&ADMIN, &command(1, 0, &[]))), 0);
+
+    // deposit of 10 units of token 0 to the player with pid [2, 3]
+    assert_eq!(code(harness.send(&PLAYER, &command(3, 1, &[2, 3, 0, 10]))), ERROR_NOT_ADMIN);
+    assert_eq!(harness.decode_error(ERROR_NOT_ADMIN), "NotAdmin");
+    assert_eq!(code(harness.send(&ADMIN, &command(3, 1, &[2, 3, 0, 10]))), 0);
+    let state: Value = serde_json::from_str(&harness.get_state(&PLAYER)).unwrap();
+    assert_eq!(state["data"]["balance"].as_u64(), Some(10));
+
+    // hand the admin role over to the player
+    assert_eq!(code(harness.send(&ADMIN, &command(0xfe, 2, &[1, 2, 3]))), 0);
+    assert_eq!(code(harness.send(&PLAYER, &command(0xfe, 2, &[0, 6, 7]))), 0);
+    assert_eq!(code(harness.send(&ADMIN, &command(3, 3, &[2, 3, 0, 10]))), ERROR_NOT_ADMIN);
+    assert_eq!(code(harness.send(&PLAYER, &command(0xfe, 3, &[0, 2, 3]))), ERROR_LAST_ADMIN);
+}
+
+#[test]
+fn test_tick_requires_admin() {
+    let harness = Harness::<App>::new();
+    assert_eq!(code(harness.send(&PLAYER, &command(0, 0, &[]))), ERROR_NOT_ADMIN);
+    assert_eq!(harness.decode_error(ERROR_NOT_ADMIN), "NotAdmin");
+    assert_eq!(code(harness.send(&ADMIN, &command(0, 0, &[]))), 0);
+    assert_eq!(
+        harness.snapshot(),
+        serde_json::json!({"tick": 1}).to_string()
+    );
+}
+
+#[test]
+fn test_replay_guard() {
+    let harness = Harness::<App>::new();
+    // the nonce of a failed transaction is consumed as well
+    assert_eq!(code(harness.send(&PLAYER, &command(4, 0, &[]))), 2);
+    assert_eq!(code(harness.send(&PLAYER, &command(1, 1, &[]))), 0);
+    let state: Value = serde_json::from_str(&harness.get_state(&PLAYER)).unwrap();
+    assert_eq!(state["nonce"].as_u64(), Some(2));
+    assert_eq!(zkwasm_settlement_test::signature_domain(), SIGNATURE_DOMAIN);
+}
+
+#[test]
+fn test_signature_domain() {
+    let harness = Harness::<App>::new();
+    let signer = Signer::new(42);
+    let install = command(1, 0, &[]);
+    assert_eq!(code(harness.send_signed(&signer, &install)), 0);
+
+    // a signature of the same command for another deployment is rejected
+    let params = harness.sign(&signer, 0, &command(1, 1, &[]));
+    let verify = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| harness.verify(&params)));
+    assert!(verify.is_err());
+    let params = harness.sign(&signer, SIGNATURE_DOMAIN, &command(1, 1, &[]));
+    harness.verify(&params);
+}
+
+#[test]
+fn test_withdraw_settlement() {
+    let harness = Harness::<App>::new();
+    assert_eq!(code(harness.send(&PLAYER, &command(1, 0, &[]))), 0);
+    assert_eq!(code(harness.send(&ADMIN, &command(1, 0, &[]))), 0);
+    assert_eq!(code(harness.send(&ADMIN, &command(3, 1, &[2, 3, 0, 100]))), 0);
+
+    // the example settles in the tagged format, small amounts included
+    assert_eq!(code(harness.send(&PLAYER, &command(2, 1, &[0, 9 << 32, 0, 0, 60]))), 0);
+    let bytes = harness.finalize();
+    assert_eq!(bytes.len(), 57);
+    assert_eq!(bytes[0], zkwasm_rest_abi::WITHDRAW_RECORD_VERSION);
+    assert_eq!(&bytes[49..], &60u64.to_be_bytes());
+
+    // the legacy withdraw settles token 0 whatever its params[1], the token withdraw reads it
+    assert_eq!(code(harness.send(&PLAYER, &command(2, 2, &[7, 5, 0, 0]))), 0);
+    assert_eq!(code(harness.send(&PLAYER, &command(0xfd, 3, &[1, 5, 0, 0]))), ERROR_UNSUPPORTED_TOKEN);
+    assert_eq!(code(harness.send(&PLAYER, &command(0xfd, 4, &[0, 5, 0, 0]))), 0);
+    let bytes = harness.finalize();
+    assert_eq!(bytes.len(), 2 * 57);
+    assert_eq!(&bytes[1..5], &0u32.to_le_bytes());
+}
+
+#[test]
+fn test_harness_reset() {
+    {
+        let harness = Harness::<App>::new();
+        assert_eq!(code(harness.send(&ADMIN, &command(0, 0, &[]))), 0);
+        assert_eq!(code(harness.send(&PLAYER, &command(1, 0, &[]))), 0);
+        assert_eq!(code(harness.send(&ADMIN, &command(1, 1, &[]))), 0);
+        assert_eq!(code(harness.send(&ADMIN, &command(3, 2, &[2, 3, 0, 100]))), 0);
+        assert_eq!(code(harness.send(&PLAYER, &command(2, 1, &[0, 9 << 32, 0, 0, 60]))), 0);
+    }
+
+    // neither the tick nor the withdraw of the dropped harness were stored
+    let harness = Harness::<App>::new();
+    assert_eq!(
+        harness.snapshot(),
+        serde_json::json!({"tick": 0}).to_string()
+    );
+    assert!(harness.finalize().is_empty());
+}
+
+fn snapshot(entries: &[Value]) -> String {
+    entries.iter().map(|e| e.to_string() + "\n").collect()
+}
+
+fn header(version: u32, root: [u64; 4]) -> Value {
+    let root = root.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>();
+    serde_json::json!({"header": {"version": version, "depth": 32, "root": root}})
+}
+
+fn leaf(index: u64, data: [u8; 32]) -> Value {
+    serde_json::json!({"leaf": {"index": index.to_string(), "data": data}})
+}
+
+fn record(data: &str) -> Value {
+    serde_json::json!({"record": {"hash": [1u8; 32], "data": data}})
+}
+
+#[test]
+fn test_load_snapshot() {
+    let harness = Harness::<App>::new();
+    let first = (1u64 << 32) - 1;
+    let load = |entries: &[Value]| harness.load_snapshot(snapshot(entries).as_bytes());
+
+    // clearing a leaf keeps the empty root
+    let root = load(&[header(1, EMPTY_ROOT), leaf(first, [0; 32]), record("0100000000000000")]);
+    assert_eq!(root, Ok(EMPTY_ROOT));
+
+    assert!(load(&[header(2, EMPTY_ROOT)]).unwrap_err().contains("version"));
+    assert!(load(&[leaf(first, [0; 32])]).unwrap_err().contains("no header"));
+    assert!(load(&[header(1, EMPTY_ROOT), leaf(5, [0; 32])]).unwrap_err().contains("leaf index"));
+    assert!(load(&[header(1, EMPTY_ROOT), record("010203")]).unwrap_err().contains("3 bytes"));
+    assert!(load(&[header(1, EMPTY_ROOT), leaf(first, [1; 32])]).unwrap_err().contains("mismatch"));
+
+    // a bundle that does not start from the root of the snapshot is rejected before it runs
+    let state = snapshot(&[header(1, EMPTY_ROOT)]);
+    let err = harness.replay_snapshot(state.as_bytes(), &[1, 2, 3, 4], &[0], false).unwrap_err();
+    assert!(err.contains("does not match the pre root"));
+}
+
+#[test]
+fn test_replay() {
+    let signer = Signer::new(7);
+    let commands = [command(1, 0, &[]), command(4, 1, &[])];
+    let (public, private) = {
+        let harness = Harness::<App>::new();
+        let mut private = vec![commands.len() as u64];
+        for command in commands.iter() {
+            private.extend(harness.sign(&signer, SIGNATURE_DOMAIN, command));
+        }
+        (EMPTY_ROOT.to_vec(), private)
+    };
+
+    let (report, root) = {
+        let harness = Harness::<App>::new();
+        let report = harness.replay(&public, &private, true).unwrap();
+        (report, harness.root())
+    };
+    assert_eq!(report.initial_root, EMPTY_ROOT);
+    assert_eq!(report.txs.len(), 2);
+    assert!(report.txs.iter().all(|tx| TxResult::from_slice(&tx.result).unwrap().code == 0));
+    assert_eq!((report.txs[1].command, report.txs[1].nonce), (4, 1));
+    assert!(report.preempt);
+    assert_eq!(report.root, root);
+    // root, settlement, pre root and bundle counter as listed by the example
+    assert_eq!(report.outputs.len(), 13);
+    assert_eq!(&report.outputs[..4], &root);
+    assert_eq!(&report.outputs[8..12], &EMPTY_ROOT);
+
+    // the replay ends in the state of the same transactions sent to the app
+    let harness = Harness::<App>::new();
+    for command in commands.iter() {
+        assert_eq!(code(harness.send_signed(&signer, command)), 0);
+    }
+    harness.finalize();
+    assert_eq!(harness.root(), root);
+
+    // inputs cut short are reported instead of panicking
+    let err = harness.replay(&public, &private[..30], true).unwrap_err();
+    assert!(err.contains("private inputs exhausted"));
+}
+
+#[test]
+fn test_tx_changes() {
+    let harness = Harness::<App>::new();
+    let guard_key = [2, 3, 0xfc00, 0xfc01];
+    let player_key = [2, 3, 0xff00, 0xff01];
+    let keys = |result: &TxResult| result.changes.iter().map(|c| c.key).collect::<Vec<_>>();
+    let result = TxResult::from_slice(&harness.send(&PLAYER, &command(1, 0, &[]))).unwrap();
+    assert_eq!(keys(&result), vec![player_key, guard_key]);
+    // the player record holds the nonce of its next transaction first
+    assert_eq!(result.changes[0].data[0], 1);
+    assert_eq!(result.changes[1].data, vec![1]);
+    assert_eq!(result.root, harness.root());
+
+    // a failed install only consumes the nonce, of the guard and of the player
+    let result = TxResult::from_slice(&harness.send(&PLAYER, &command(1, 1, &[]))).unwrap();
+    assert_eq!(result.code, 1);
+    assert_eq!(keys(&result), vec![guard_key, player_key]);
+    assert_eq!(result.changes[0].data, vec![2]);
+    assert_eq!(result.changes[1].data[0], 2);
+}
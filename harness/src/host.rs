@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use zkwasmhostlocal::context::datacache::CacheContext;
+use zkwasmhostlocal::context::jubjub::sum::BabyJubjubSumContext;
+use zkwasmhostlocal::context::memory::MemoryBackend;
+use zkwasmhostlocal::context::merkle::MerkleContext;
+use zkwasmhostlocal::context::poseidon::PoseidonContext;
+
+/// Native implementation of the host functions that zkwasm-rust-sdk imports.
+///
+/// The merkle tree and data records live in memory, so the whole state is lost when the
+/// host is reset.
+pub struct NativeHost {
+    pub merkle: MerkleContext<MemoryBackend>,
+    pub datacache: CacheContext<MemoryBackend>,
+    pub poseidon: PoseidonContext,
+    pub jubjub: BabyJubjubSumContext,
+    pub witness: VecDeque<u64>,
+    pub public_inputs: VecDeque<u64>,
+    pub private_inputs: VecDeque<u64>,
+    pub outputs: Vec<u64>,
+    pub debug: bool,
+}
+
+impl NativeHost {
+    pub fn new() -> Self {
+        NativeHost {
+            merkle: MerkleContext::new(0),
+            datacache: CacheContext::new(),
+            poseidon: PoseidonContext::default(0),
+            jubjub: BabyJubjubSumContext::default(0),
+            witness: VecDeque::new(),
+            public_inputs: VecDeque::new(),
+            private_inputs: VecDeque::new(),
+            outputs: vec![],
+            debug: std::env::var("ZKWASM_HARNESS_DEBUG").is_ok(),
+        }
+    }
+}
+
+impl Default for NativeHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref HOST: Mutex<NativeHost> = Mutex::new(NativeHost::new());
+}
+
+fn host() -> std::sync::MutexGuard<'static, NativeHost> {
+    HOST.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_input(is_public: u32) -> u64 {
+    let mut host = host();
+    let inputs = if is_public == 1 {
+        &mut host.public_inputs
+    } else {
+        &mut host.private_inputs
+    };
+    inputs.pop_front().expect("wasm_input: no more inputs")
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_output(v: u64) {
+    host().outputs.push(v);
+}
+
+#[no_mangle]
+pub extern "C" fn require(cond: bool) {
+    if !cond {
+        panic!("zkwasm require failed");
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_dbg(v: u64) {
+    if host().debug {
+        println!("wasm_dbg: {}", v);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_dbg_char(v: u64) {
+    if host().debug {
+        print!("{}", v as u8 as char);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_trace_size() -> u64 {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_witness_insert(v: u64) {
+    host().witness.push_back(v);
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_witness_pop() -> u64 {
+    host().witness.pop_front().expect("wasm_witness_pop: witness is empty")
+}
+
+#[no_mangle]
+pub extern "C" fn cache_set_mode(mode: u64) {
+    host().datacache.set_mode(mode);
+}
+
+#[no_mangle]
+pub extern "C" fn cache_set_hash(arg: u64) {
+    host().datacache.set_data_hash(arg);
+}
+
+#[no_mangle]
+pub extern "C" fn cache_store_data(data: u64) {
+    host().datacache.store_data(data);
+}
+
+#[no_mangle]
+pub extern "C" fn cache_fetch_data() -> u64 {
+    host().datacache.fetch_data()
+}
+
+#[no_mangle]
+pub extern "C" fn poseidon_new(arg: u64) {
+    host().poseidon.poseidon_new(arg as usize);
+}
+
+#[no_mangle]
+pub extern "C" fn poseidon_push(arg: u64) {
+    host().poseidon.poseidon_push(arg);
+}
+
+#[no_mangle]
+pub extern "C" fn poseidon_finalize() -> u64 {
+    host().poseidon.poseidon_finalize()
+}
+
+#[no_mangle]
+pub extern "C" fn babyjubjub_sum_new(arg: u64) {
+    host().jubjub.babyjubjub_sum_new(arg as usize);
+}
+
+#[no_mangle]
+pub extern "C" fn babyjubjub_sum_push(arg: u64) {
+    host().jubjub.babyjubjub_sum_push(arg);
+}
+
+#[no_mangle]
+pub extern "C" fn babyjubjub_sum_finalize() -> u64 {
+    host().jubjub.babyjubjub_sum_finalize()
+}
+
+#[no_mangle]
+pub extern "C" fn merkle_setroot(arg: u64) {
+    host().merkle.merkle_setroot(arg);
+}
+
+#[no_mangle]
+pub extern "C" fn merkle_getroot() -> u64 {
+    host().merkle.merkle_getroot()
+}
+
+#[no_mangle]
+pub extern "C" fn merkle_address(arg: u64) {
+    host().merkle.merkle_address(arg);
+}
+
+#[no_mangle]
+pub extern "C" fn merkle_set(arg: u64) {
+    host().merkle.merkle_set(arg);
+}
+
+#[no_mangle]
+pub extern "C" fn merkle_get() -> u64 {
+    host().merkle.merkle_get()
+}
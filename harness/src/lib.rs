@@ -0,0 +1,202 @@
+//! Native execution harness for applications built with `create_zkwasm_apis!`.
+//!
+//! The harness exports the host functions that zkwasm-rust-sdk imports (see [`host`]) and backs
+//! them with the in-memory contexts of the bootstrap host, so an application crate can be linked
+//! natively and driven from `cargo test`:
+//!
+//! ```ignore
+//! zkwasm_rest_harness::harness_app!(App, my_app);
+//!
+//! #[test]
+//! fn install() {
+//!     let harness = Harness::<App>::new();
+//!     let r = harness.send(&[1, 2, 3, 4], &command(1, 0, &[]));
+//!     assert_eq!(r, vec![0]);
+//! }
+//! ```
+//!
+//! The host state is global, so harnesses are serialized through a lock held for their lifetime.
+//! `Harness::new` resets the host and the global state of the application, through the `reset`
+//! generated by `create_zkwasm_apis!`, so tests do not depend on each other.
+use std::marker::PhantomData;
+use std::sync::{Mutex, MutexGuard};
+
+pub mod host;
+pub mod replay;
+pub mod signer;
+
+use host::{NativeHost, HOST};
+use signer::Signer;
+pub use zkwasm_rest_abi::ApiOptions;
+pub use zkwasm_rest_abi::bundle::{BundleInput, BundleRun};
+
+/// Entry points generated by `create_zkwasm_apis!`, see [`harness_app!`]
+pub trait ZkwasmApp {
+    const OPTIONS: ApiOptions;
+    fn reset();
+    fn initialize(root: Vec<u64>);
+    fn verify_tx_signature(params: Vec<u64>);
+    fn handle_tx(params: Vec<u64>) -> Vec<u64>;
+    fn preempt() -> bool;
+    fn finalize() -> Vec<u8>;
+    fn snapshot() -> String;
+    fn get_state(pkey: Vec<u64>) -> String;
+    fn decode_error(e: u32) -> String;
+    fn run_bundle<I: BundleInput>(
+        input: &mut I,
+        verify_signature: bool,
+        on_tx: impl FnMut(usize, u64, &[u64]),
+    ) -> Result<BundleRun, String>;
+}
+
+/// Implement [`ZkwasmApp`] for `$name` by forwarding to the functions that
+/// `create_zkwasm_apis!` generated in the module `$app`
+#[macro_export]
+macro_rules! harness_app {
+    ($name: ident, $($app: ident)::+) => {
+        pub struct $name;
+        impl $crate::ZkwasmApp for $name {
+            const OPTIONS: $crate::ApiOptions = $($app)::+::API_OPTIONS;
+            fn reset() {
+                $($app)::+::reset()
+            }
+            fn initialize(root: Vec<u64>) {
+                $($app)::+::initialize(root)
+            }
+            fn verify_tx_signature(params: Vec<u64>) {
+                $($app)::+::verify_tx_signature(params)
+            }
+            fn handle_tx(params: Vec<u64>) -> Vec<u64> {
+                $($app)::+::handle_tx(params)
+            }
+            fn preempt() -> bool {
+                $($app)::+::preempt()
+            }
+            fn finalize() -> Vec<u8> {
+                $($app)::+::finalize()
+            }
+            fn snapshot() -> String {
+                $($app)::+::snapshot()
+            }
+            fn get_state(pkey: Vec<u64>) -> String {
+                $($app)::+::get_state(pkey)
+            }
+            fn decode_error(e: u32) -> String {
+                $($app)::+::decode_error(e)
+            }
+            fn run_bundle<I: $crate::BundleInput>(
+                input: &mut I,
+                verify_signature: bool,
+                on_tx: impl FnMut(usize, u64, &[u64]),
+            ) -> Result<$crate::BundleRun, String> {
+                $($app)::+::run_bundle(input, verify_signature, on_tx)
+            }
+        }
+    };
+}
+
+lazy_static::lazy_static! {
+    static ref HARNESS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// The root of the empty merkle map
+pub const EMPTY_ROOT: [u64; 4] = [
+    14789582351289948625,
+    10919489180071018470,
+    10309858136294505219,
+    2839580074036780766,
+];
+
+/// Encode a command as expected by `handle_tx`: the first word carries the command id, the
+/// length of the command in words and the nonce
+pub fn command(cmd: u64, nonce: u64, args: &[u64]) -> Vec<u64> {
+    let mut words = vec![cmd + (((args.len() + 1) as u64) << 8) + (nonce << 16)];
+    words.extend_from_slice(args);
+    words
+}
+
+pub struct Harness<A: ZkwasmApp> {
+    _lock: MutexGuard<'static, ()>,
+    app: PhantomData<A>,
+}
+
+impl<A: ZkwasmApp> Harness<A> {
+    /// Start from an empty merkle map and a fresh global state of the app
+    pub fn new() -> Self {
+        let lock = HARNESS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        *HOST.lock().unwrap_or_else(|e| e.into_inner()) = NativeHost::new();
+        A::reset();
+        A::initialize(EMPTY_ROOT.to_vec());
+        Harness {
+            _lock: lock,
+            app: PhantomData,
+        }
+    }
+
+    /// Reload the application from `root`, which must have been produced by this harness
+    pub fn load(&self, root: [u64; 4]) {
+        A::initialize(root.to_vec());
+    }
+
+    /// Handle a transaction of the player `pkey` without checking its signature
+    pub fn send(&self, pkey: &[u64; 4], command: &[u64]) -> Vec<u64> {
+        let mut params = Vec::with_capacity(20 + command.len());
+        params.extend_from_slice(pkey);
+        params.resize(20, 0);
+        params.extend_from_slice(command);
+        A::handle_tx(params)
+    }
+
+    /// The params of a transaction of `signer` signed under `domain`, as read by `zkmain`
+    pub fn sign(&self, signer: &Signer, domain: u64, command: &[u64]) -> Vec<u64> {
+        let msg = zkwasm_rest_abi::guard::message_hash(domain, command);
+        let mut params = Vec::with_capacity(20 + command.len());
+        params.extend_from_slice(&signer.public_key());
+        params.extend_from_slice(&signer.sign(&msg));
+        params.extend_from_slice(command);
+        params
+    }
+
+    /// Verify the signature of `params` as `zkmain` does, panicking when it is rejected
+    pub fn verify(&self, params: &[u64]) {
+        A::verify_tx_signature(params.to_vec());
+    }
+
+    /// Sign a transaction of `signer` under the domain of the app, verify and handle it
+    pub fn send_signed(&self, signer: &Signer, command: &[u64]) -> Vec<u64> {
+        let params = self.sign(signer, A::OPTIONS.domain, command);
+        self.verify(&params);
+        A::handle_tx(params)
+    }
+
+    pub fn preempt(&self) -> bool {
+        A::preempt()
+    }
+
+    /// Store the global state and return the settlement bytes of the bundle
+    pub fn finalize(&self) -> Vec<u8> {
+        A::finalize()
+    }
+
+    pub fn snapshot(&self) -> String {
+        A::snapshot()
+    }
+
+    pub fn get_state(&self, pkey: &[u64; 4]) -> String {
+        A::get_state(pkey.to_vec())
+    }
+
+    pub fn decode_error(&self, e: u32) -> String {
+        A::decode_error(e)
+    }
+
+    pub fn root(&self) -> [u64; 4] {
+        unsafe { zkwasm_rest_abi::MERKLE_MAP.merkle.root }
+    }
+}
+
+impl<A: ZkwasmApp> Default for Harness<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,343 @@
+//! Replay of a recorded bundle.
+//!
+//! A bundle is recorded as the `wasm_input` streams consumed by `zkmain`: the private stream
+//! starts with the number of transactions followed by the params of every transaction, and the
+//! public stream holds the initial root. The replay runs the `run_bundle` generated by
+//! `create_zkwasm_apis!`, which `zkmain` runs as well, and reports the merkle root after every
+//! transaction.
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+use zkwasm_rest_abi::bundle::BundleInput;
+use zkwasmhostlocal::context::backend::HostBackend;
+use zkwasmhostlocal::context::merkle::MERKLE_TREE_HEIGHT;
+
+use crate::host::{NativeHost, HOST};
+use crate::{Harness, ZkwasmApp};
+
+/// The only snapshot format written by csm_service
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A u64 of a recorded stream, either a json number or a decimal or `0x` prefixed string
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Word {
+    Number(u64),
+    Text(String),
+}
+
+impl Word {
+    pub fn value(&self) -> Result<u64, String> {
+        match self {
+            Word::Number(v) => Ok(*v),
+            Word::Text(s) => match s.strip_prefix("0x") {
+                Some(h) => u64::from_str_radix(h, 16),
+                None => s.parse::<u64>(),
+            }
+            .map_err(|e| format!("invalid input word {}: {}", s, e)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BundleInputs {
+    pub public: Vec<Word>,
+    pub private: Vec<Word>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TxReport {
+    pub index: usize,
+    pub command: u64,
+    pub nonce: u64,
+    pub result: Vec<u64>,
+    pub root: [u64; 4],
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplayReport {
+    pub initial_root: [u64; 4],
+    pub txs: Vec<TxReport>,
+    pub preempt: bool,
+    pub root: [u64; 4],
+    /// `conclude_settlement` of the settlement, the last four outputs of `zkmain`
+    pub txdata: [u64; 4],
+    pub settlement: String, // hex encoding
+    /// Outputs of `zkmain` laid out by `ApiOptions::outputs`
+    pub outputs: Vec<u64>,
+}
+
+/// Entries of a snapshot exported by csm_service, see `dbservice/src/snapshot.rs`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SnapshotEntry {
+    Header {
+        version: u32,
+        depth: usize,
+        root: [u8; 32],
+    },
+    Leaf {
+        index: String,
+        data: [u8; 32],
+    },
+    Record {
+        hash: [u8; 32],
+        data: String,
+    },
+}
+
+fn bytes_to_limbs(bytes: &[u8; 32]) -> [u64; 4] {
+    bytes
+        .chunks(8)
+        .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap()
+}
+
+struct Stream<'a> {
+    name: &'static str,
+    words: std::slice::Iter<'a, u64>,
+}
+
+impl<'a> Stream<'a> {
+    fn next(&mut self) -> Result<u64, String> {
+        self.words
+            .next()
+            .cloned()
+            .ok_or_else(|| format!("{} inputs exhausted", self.name))
+    }
+}
+
+/// Recorded input streams of a bundle
+struct Recorded<'a> {
+    public: Stream<'a>,
+    private: Stream<'a>,
+}
+
+impl<'a> BundleInput for Recorded<'a> {
+    fn public(&mut self) -> Result<u64, String> {
+        self.public.next()
+    }
+    fn private(&mut self) -> Result<u64, String> {
+        self.private.next()
+    }
+}
+
+/// Load a csm_service snapshot into the in-memory merkle map and data records of `host` and
+/// return its root
+pub fn load_snapshot<R: BufRead>(host: &mut NativeHost, reader: R) -> Result<[u64; 4], String> {
+    let mut root = host.merkle.backend.empty_root();
+    let mut expected = None;
+    let first_leaf = (1u64 << MERKLE_TREE_HEIGHT) - 1;
+    let last_leaf = (1u64 << (MERKLE_TREE_HEIGHT + 1)) - 2;
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: SnapshotEntry = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+        match entry {
+            SnapshotEntry::Header { version, depth, root } => {
+                if version != SNAPSHOT_VERSION {
+                    return Err(format!("unsupported snapshot version {}", version));
+                }
+                if depth != MERKLE_TREE_HEIGHT {
+                    return Err(format!("unsupported snapshot depth {}", depth));
+                }
+                expected = Some(root);
+            }
+            _ if expected.is_none() => return Err("snapshot has no header".to_string()),
+            SnapshotEntry::Leaf { index, data } => {
+                let index = index
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid leaf index {}: {}", index, e))?;
+                if index < first_leaf || index > last_leaf {
+                    return Err(format!("invalid leaf index {}", index));
+                }
+                root = host.merkle.backend.update_leaf(root, index, data);
+            }
+            SnapshotEntry::Record { hash, data } => {
+                let bytes = hex::decode(&data).map_err(|e| e.to_string())?;
+                if bytes.len() % 8 != 0 {
+                    return Err(format!(
+                        "record {} of {} bytes is not a sequence of u64",
+                        hex::encode(hash),
+                        bytes.len()
+                    ));
+                }
+                let data = bytes
+                    .chunks_exact(8)
+                    .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+                    .collect::<Vec<_>>();
+                host.datacache.backend.update_record(hash, data);
+            }
+        }
+    }
+    match expected {
+        None => Err("snapshot has no header".to_string()),
+        Some(expected) if expected != root => Err(format!(
+            "snapshot root mismatch: expected {:?}, rebuilt {:?}",
+            bytes_to_limbs(&expected),
+            bytes_to_limbs(&root)
+        )),
+        Some(_) => Ok(bytes_to_limbs(&root)),
+    }
+}
+
+impl<A: ZkwasmApp> Harness<A> {
+    /// Load a csm_service snapshot into the in-memory merkle map and return its root
+    pub fn load_snapshot<R: BufRead>(&self, reader: R) -> Result<[u64; 4], String> {
+        load_snapshot(&mut HOST.lock().unwrap_or_else(|e| e.into_inner()), reader)
+    }
+
+    /// Load a csm_service snapshot and replay a bundle on top of it. The bundle must start from the
+    /// root of the snapshot, other roots are not in the merkle map and would fail deep in the host.
+    pub fn replay_snapshot<R: BufRead>(
+        &self,
+        snapshot: R,
+        public: &[u64],
+        private: &[u64],
+        verify_signature: bool,
+    ) -> Result<ReplayReport, String> {
+        let root = self.load_snapshot(snapshot)?;
+        let pre_root = public.get(..4).ok_or("public inputs exhausted")?;
+        if pre_root != root {
+            return Err(format!(
+                "snapshot root {:?} does not match the pre root {:?} of the bundle",
+                root, pre_root
+            ));
+        }
+        self.replay(public, private, verify_signature)
+    }
+
+    /// Run the `zkmain` flow on recorded inputs and report the root after every transaction
+    pub fn replay(
+        &self,
+        public: &[u64],
+        private: &[u64],
+        verify_signature: bool,
+    ) -> Result<ReplayReport, String> {
+        let mut input = Recorded {
+            public: Stream {
+                name: "public",
+                words: public.iter(),
+            },
+            private: Stream {
+                name: "private",
+                words: private.iter(),
+            },
+        };
+        let mut txs = vec![];
+        let run = A::run_bundle(&mut input, verify_signature, |index, command, result| {
+            txs.push(TxReport {
+                index,
+                command: command & 0xff,
+                nonce: command >> 16,
+                result: result.to_vec(),
+                root: self.root(),
+            })
+        })?;
+        Ok(ReplayReport {
+            initial_root: run.outputs.pre_root,
+            txs,
+            preempt: run.preempt,
+            root: run.outputs.root,
+            txdata: run.outputs.settlement,
+            settlement: hex::encode(&run.settlement),
+            outputs: run.outputs.words(A::OPTIONS.outputs),
+        })
+    }
+}
+
+fn read_inputs(path: &str) -> Result<(Vec<u64>, Vec<u64>), String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let inputs: BundleInputs = serde_json::from_reader(file).map_err(|e| e.to_string())?;
+    let public = inputs.public.iter().map(Word::value).collect::<Result<_, _>>()?;
+    let private = inputs.private.iter().map(Word::value).collect::<Result<_, _>>()?;
+    Ok((public, private))
+}
+
+const USAGE: &str = "usage: replay <inputs.json> [--snapshot <state.jsonl>] [--expected <roots.json>] [--no-signature] [--json]";
+
+fn run<A: ZkwasmApp>(args: Vec<String>) -> Result<bool, String> {
+    let mut inputs = None;
+    let mut snapshot = None;
+    let mut expected = None;
+    let mut verify_signature = true;
+    let mut json = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--snapshot" => snapshot = Some(args.next().ok_or(USAGE)?),
+            "--expected" => expected = Some(args.next().ok_or(USAGE)?),
+            "--no-signature" => verify_signature = false,
+            "--json" => json = true,
+            _ if inputs.is_none() && !arg.starts_with("--") => inputs = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let (public, private) = read_inputs(&inputs.ok_or(USAGE)?)?;
+    let expected: Option<Vec<[u64; 4]>> = match expected {
+        None => None,
+        Some(path) => {
+            let file = std::fs::File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+            Some(serde_json::from_reader(file).map_err(|e| e.to_string())?)
+        }
+    };
+
+    let harness = Harness::<A>::new();
+    let report = match snapshot {
+        Some(path) => {
+            let file = std::fs::File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+            let reader = std::io::BufReader::new(file);
+            harness.replay_snapshot(reader, &public, &private, verify_signature)?
+        }
+        None => harness.replay(&public, &private, verify_signature)?,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        println!("initial root {:?}", report.initial_root);
+    }
+    let mut diverged = false;
+    for tx in report.txs.iter() {
+        let expected_root = expected.as_ref().and_then(|e| e.get(tx.index));
+        let mark = match expected_root {
+            Some(root) if *root != tx.root => {
+                diverged = true;
+                format!(" DIVERGED, expected {:?}", root)
+            }
+            _ => String::new(),
+        };
+        let line = format!(
+            "tx {} command {} nonce {} result {:?} root {:?}{}",
+            tx.index, tx.command, tx.nonce, tx.result, tx.root, mark
+        );
+        if !json {
+            println!("{}", line);
+        } else if !mark.is_empty() {
+            eprintln!("{}", line);
+        }
+    }
+    if !json {
+        println!("preempt {}", report.preempt);
+        println!("root {:?}", report.root);
+        println!("txdata {:?}", report.txdata);
+        println!("outputs {:?}", report.outputs);
+    }
+    Ok(!diverged)
+}
+
+/// Command line entry of a replay binary for the app `A`, e.g. `examples/replay.rs`
+pub fn main<A: ZkwasmApp>() {
+    match run::<A>(std::env::args().skip(1).collect()) {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
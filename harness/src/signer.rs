@@ -0,0 +1,98 @@
+//! BabyJubjub keys for signing transactions in tests.
+//!
+//! A signature `(R, s)` of the message hash `c` by the key `k` with `pk = k·G` is accepted by the
+//! ABI when `c·pk + R - s·G` is the identity, so the signer picks `R = r·G` and
+//! `s = r + c·k mod l`. The nonce `r` is derived from the key and the message, which is enough for
+//! tests but not for keys that hold anything of value.
+use halo2curves::bn256::Fr;
+use halo2curves::ff::PrimeField;
+use lazy_static::lazy_static;
+use num_bigint::BigUint;
+use zkwasmhostlocal::jubjub::Point;
+
+lazy_static! {
+    /// The order of the subgroup generated by `G`
+    static ref ORDER: BigUint = BigUint::parse_bytes(
+        b"2736030358979909402780800718157159386076813972158567259200215660948447373041",
+        10
+    )
+    .unwrap();
+    /// The generator, the negation of the base point used by the verifier
+    static ref GENERATOR: Point = Point {
+        x: -to_field(&BigUint::parse_bytes(
+            b"017054bebd8ed76269b84220f215264ea2e9cc2c72ec13c846bfd7d39d28920a",
+            16
+        )
+        .unwrap()),
+        y: to_field(&BigUint::parse_bytes(
+            b"05a01167ea785d3f784224644a68e4067532c815f5f6d57d984b5c0e9c6c94b7",
+            16
+        )
+        .unwrap()),
+    };
+}
+
+fn to_field(n: &BigUint) -> Fr {
+    let mut bytes = n.to_bytes_le();
+    bytes.resize(32, 0);
+    Fr::from_repr(bytes.try_into().unwrap()).unwrap()
+}
+
+fn to_limbs(f: &Fr) -> [u64; 4] {
+    let repr = f.to_repr();
+    let mut limbs = [0; 4];
+    for (limb, chunk) in limbs.iter_mut().zip(repr.as_ref().chunks(8)) {
+        *limb = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    limbs
+}
+
+fn from_limbs(limbs: &[u64]) -> BigUint {
+    BigUint::from_slice(
+        &limbs
+            .iter()
+            .flat_map(|l| [*l as u32, (*l >> 32) as u32])
+            .collect::<Vec<_>>(),
+    )
+}
+
+pub struct Signer {
+    secret: BigUint,
+    pk: Point,
+}
+
+impl Signer {
+    pub fn new(secret: u64) -> Self {
+        let secret = BigUint::from(secret) % &*ORDER;
+        let pk = GENERATOR.mul_scalar(&secret);
+        Signer { secret, pk }
+    }
+
+    /// The public key as passed in the first 8 words of a transaction, x then y
+    pub fn public_key(&self) -> [u64; 8] {
+        let mut words = [0; 8];
+        words[..4].copy_from_slice(&to_limbs(&self.pk.x));
+        words[4..].copy_from_slice(&to_limbs(&self.pk.y));
+        words
+    }
+
+    /// The key of the player as passed to `Harness::send` and `Harness::get_state`
+    pub fn pkey(&self) -> [u64; 4] {
+        to_limbs(&self.pk.x)
+    }
+
+    /// Sign the message hash `msg`, returning the words of `R` followed by the words of `s`
+    pub fn sign(&self, msg: &[u64; 4]) -> [u64; 12] {
+        let c = from_limbs(msg);
+        let r = (&self.secret * 31u32 + &c + 1u32) % &*ORDER;
+        let sig_r = GENERATOR.mul_scalar(&r);
+        let s = (&r + &c * &self.secret) % &*ORDER;
+        let mut s_limbs = s.to_u64_digits();
+        s_limbs.resize(4, 0);
+        let mut words = [0; 12];
+        words[..4].copy_from_slice(&to_limbs(&sig_r.x));
+        words[4..8].copy_from_slice(&to_limbs(&sig_r.y));
+        words[8..].copy_from_slice(&s_limbs);
+        words
+    }
+}
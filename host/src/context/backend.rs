@@ -0,0 +1,83 @@
+/// Storage operations required by the merkle and data cache contexts.
+///
+/// Roots and leaves are 32 bytes. `index` is the node index of a leaf in the merkle tree
+/// (`address + (1 << MERKLE_TREE_HEIGHT) - 1`). Data records are keyed by the hash of their data.
+pub trait HostBackend {
+    /// Update the leaf at `index` of the tree identified by `root` and return the new root
+    fn update_leaf(&mut self, root: [u8; 32], index: u64, data: [u8; 32]) -> [u8; 32];
+    /// Read the leaf at `index` of the tree identified by `root`, empty leaves are all zero
+    fn get_leaf(&mut self, root: [u8; 32], index: u64) -> [u8; 32];
+    fn update_record(&mut self, hash: [u8; 32], data: Vec<u64>);
+    /// Read a data record, unknown records are empty
+    fn get_record(&mut self, hash: [u8; 32]) -> Vec<u64>;
+}
+
+/// Payload bytes exchanged with a backend, roots, hashes and leaves count 32 bytes and indexes and
+/// record words 8 bytes
+#[derive(Clone, Debug, Default)]
+pub struct BackendTraffic {
+    pub calls: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl BackendTraffic {
+    fn record(&mut self, sent: usize, received: usize) {
+        self.calls += 1;
+        self.bytes_sent += sent as u64;
+        self.bytes_received += received as u64;
+    }
+}
+
+/// Backend that counts the payload of every operation it forwards to `inner`
+#[derive(Default)]
+pub struct Metered<B> {
+    pub inner: B,
+    pub traffic: BackendTraffic,
+}
+
+impl<B: HostBackend> HostBackend for Metered<B> {
+    fn update_leaf(&mut self, root: [u8; 32], index: u64, data: [u8; 32]) -> [u8; 32] {
+        self.traffic.record(32 + 8 + 32, 32);
+        self.inner.update_leaf(root, index, data)
+    }
+
+    fn get_leaf(&mut self, root: [u8; 32], index: u64) -> [u8; 32] {
+        self.traffic.record(32 + 8, 32);
+        self.inner.get_leaf(root, index)
+    }
+
+    fn update_record(&mut self, hash: [u8; 32], data: Vec<u64>) {
+        self.traffic.record(32 + 8 * data.len(), 0);
+        self.inner.update_record(hash, data)
+    }
+
+    fn get_record(&mut self, hash: [u8; 32]) -> Vec<u64> {
+        let data = self.inner.get_record(hash);
+        self.traffic.record(32, 8 * data.len());
+        data
+    }
+}
+
+/// Backend used by the global contexts, the JS RPC binding unless the `inmemory` feature is on
+#[cfg(not(feature = "inmemory"))]
+pub type DefaultBackend = Metered<super::rpc::RpcBackend>;
+#[cfg(feature = "inmemory")]
+pub type DefaultBackend = Metered<super::memory::MemoryBackend>;
+
+#[cfg(test)]
+mod tests {
+    use super::{HostBackend, Metered};
+    use crate::context::memory::MemoryBackend;
+
+    #[test]
+    fn test_metered_traffic() {
+        let mut backend = Metered::<MemoryBackend>::default();
+        backend.update_record([1; 32], vec![1, 2, 3]);
+        assert_eq!(backend.get_record([1; 32]), vec![1, 2, 3]);
+        assert!(backend.get_record([2; 32]).is_empty());
+        assert_eq!(backend.traffic.calls, 3);
+        assert_eq!(backend.traffic.bytes_sent, 32 + 24 + 32 + 32);
+        assert_eq!(backend.traffic.bytes_received, 24);
+    }
+}
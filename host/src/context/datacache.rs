@@ -1,47 +1,44 @@
 use bytes_helper::Reduce;
 use bytes_helper::ReduceRule;
 use wasm_bindgen::JsValue;
-use wasm_bindgen::prelude::wasm_bindgen;
+
+use super::backend::{DefaultBackend, HostBackend};
 
 const FETCH_MODE: u64 = 0;
 const STORE_MODE: u64 = 1;
 
-#[wasm_bindgen(raw_module = "./rpcbind.js")]
-extern "C" {
-    pub fn update_record(hash: Vec<u8>, data: Vec<u64>);
-    pub fn get_record(hash: Vec<u8>) -> js_sys::BigUint64Array;
-}
-
-pub struct CacheContext {
+pub struct CacheContext<B: HostBackend = DefaultBackend> {
     pub mode: u64,
     pub hash: Reduce,
     pub data: Vec<u64>,
     pub fetch: bool,
+    pub used_round: usize,
+    pub backend: B,
 }
 
 fn new_reduce(rules: Vec<ReduceRule>) -> Reduce {
     Reduce { cursor: 0, rules }
 }
 
-fn array_from_js_to_u64(js_array: js_sys::BigUint64Array) -> Vec<u64> {
-    let mut rust_vec = Vec::with_capacity(js_array.length() as usize);
-    for i in 0..js_array.length() {
-        rust_vec.push(js_array.get_index(i));
-    }
-    rust_vec
-}
-
 pub fn array_from_u8_to_js(data: &[u8; 32]) -> JsValue {
     JsValue::from(js_sys::Uint8Array::from(&data[..]))
 }
 
-impl CacheContext {
+impl<B: HostBackend + Default> CacheContext<B> {
     pub fn new() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B: HostBackend> CacheContext<B> {
+    pub fn with_backend(backend: B) -> Self {
         CacheContext {
             mode: 0,
             hash: new_reduce(vec![ReduceRule::Bytes(vec![], 4)]),
             fetch: false,
             data: vec![],
+            used_round: 0,
+            backend,
         }
     }
 
@@ -53,6 +50,7 @@ impl CacheContext {
     pub fn set_data_hash(&mut self, v: u64) {
         self.hash.reduce(v);
         if self.hash.cursor == 0 {
+            self.used_round += 1;
             let hash: [u8; 32] = self.hash.rules[0]
                 .bytes_value()
                 .unwrap()
@@ -60,16 +58,12 @@ impl CacheContext {
                 .unwrap();
             if self.mode == FETCH_MODE {
                 //let data = get_record(array_from_u8_to_js(&hash.clone()));
-                let data = get_record(hash.to_vec());
-                self.data = array_from_js_to_u64(data);
+                self.data = self.backend.get_record(hash);
                 self.fetch = false;
             } else if self.mode == STORE_MODE {
                 // put data and hash into mongo_datahash
                 if !self.data.is_empty() {
-                    update_record(
-                        hash.to_vec(),
-                        self.data.clone()
-                    )
+                    self.backend.update_record(hash, self.data.clone())
                 }
             }
         }
@@ -89,5 +83,3 @@ impl CacheContext {
         self.data.push(v);
     }
 }
-
-impl CacheContext {}
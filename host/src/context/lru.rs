@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+pub type LeafKey = ([u8; 32], u64);
+
+/// Bounded LRU cache of merkle leaves keyed by (root, index).
+///
+/// Leaves under a given root never change, so entries never need invalidation; they are only
+/// evicted when the cache is full.
+pub struct LeafCache {
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    entries: HashMap<LeafKey, ([u8; 32], u64)>,
+    /// last use tick of each entry, the first one is the least recently used
+    order: BTreeMap<u64, LeafKey>,
+    tick: u64,
+}
+
+impl LeafCache {
+    pub fn new(capacity: usize) -> Self {
+        LeafCache {
+            capacity,
+            hits: 0,
+            misses: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn touch(&mut self, key: LeafKey) -> u64 {
+        self.tick += 1;
+        self.order.insert(self.tick, key);
+        self.tick
+    }
+
+    pub fn get(&mut self, root: &[u8; 32], index: u64) -> Option<[u8; 32]> {
+        let key = (*root, index);
+        match self.entries.get(&key).cloned() {
+            Some((data, last)) => {
+                self.hits += 1;
+                self.order.remove(&last);
+                let tick = self.touch(key);
+                self.entries.insert(key, (data, tick));
+                Some(data)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, root: [u8; 32], index: u64, data: [u8; 32]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (root, index);
+        if let Some((_, last)) = self.entries.get(&key) {
+            self.order.remove(last);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        let tick = self.touch(key);
+        self.entries.insert(key, (data, tick));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LeafCache;
+
+    #[test]
+    fn test_leaf_cache() {
+        let mut cache = LeafCache::new(2);
+        cache.insert([0; 32], 1, [1; 32]);
+        cache.insert([0; 32], 2, [2; 32]);
+        assert_eq!(cache.get(&[0; 32], 1), Some([1; 32]));
+        // index 2 is now the least recently used entry
+        cache.insert([1; 32], 1, [3; 32]);
+        assert_eq!(cache.get(&[0; 32], 2), None);
+        assert_eq!(cache.get(&[0; 32], 1), Some([1; 32]));
+        assert_eq!(cache.get(&[1; 32], 1), Some([3; 32]));
+        assert_eq!(cache.len(), 2);
+        assert_eq!((cache.hits, cache.misses), (3, 1));
+    }
+}
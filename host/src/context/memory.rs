@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use halo2curves::bn256::Fr;
+use halo2curves::ff::PrimeField;
+
+use super::backend::HostBackend;
+use super::merkle::MERKLE_TREE_HEIGHT;
+use crate::poseidon::{MERKLE_HASHER, MERKLE_LEAF_HASHER};
+
+/// In-memory sparse merkle tree that produces the same roots as the merkle db service.
+///
+/// Nodes are stored by hash like in the db service, so every root ever produced stays readable.
+/// Empty subtrees are not stored, they are recognized by their default hash.
+pub struct MemoryBackend {
+    /// children of internal nodes
+    nodes: HashMap<[u8; 32], ([u8; 32], [u8; 32])>,
+    /// data of leaves
+    leaves: HashMap<[u8; 32], [u8; 32]>,
+    records: HashMap<[u8; 32], Vec<u64>>,
+    /// hash of the empty subtree at each depth, `defaults[0]` is the empty root
+    defaults: Vec<[u8; 32]>,
+}
+
+pub fn hash_children(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = MERKLE_HASHER.clone();
+    hasher.update(&[
+        Fr::from_repr(*left).unwrap(),
+        Fr::from_repr(*right).unwrap(),
+    ]);
+    hasher.squeeze().to_repr()
+}
+
+pub fn hash_leaf(data: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = MERKLE_LEAF_HASHER.clone();
+    let batchdata = data
+        .chunks(16)
+        .map(|x| {
+            let mut v = x.to_vec();
+            v.extend_from_slice(&[0u8; 16]);
+            Fr::from_repr(v.try_into().unwrap()).unwrap()
+        })
+        .collect::<Vec<Fr>>();
+    hasher.update(&batchdata);
+    hasher.squeeze().to_repr()
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        let mut defaults = vec![hash_leaf(&[0; 32])];
+        for _ in 0..MERKLE_TREE_HEIGHT {
+            let child = defaults.last().unwrap();
+            defaults.push(hash_children(child, child));
+        }
+        defaults.reverse();
+        MemoryBackend {
+            nodes: HashMap::new(),
+            leaves: HashMap::new(),
+            records: HashMap::new(),
+            defaults,
+        }
+    }
+}
+
+impl MemoryBackend {
+    pub fn empty_root(&self) -> [u8; 32] {
+        self.defaults[0]
+    }
+
+    fn children(&self, hash: &[u8; 32], depth: usize) -> ([u8; 32], [u8; 32]) {
+        if *hash == self.defaults[depth] {
+            (self.defaults[depth + 1], self.defaults[depth + 1])
+        } else {
+            *self
+                .nodes
+                .get(hash)
+                .unwrap_or_else(|| panic!("unknown merkle node {:?} at depth {}", hash, depth))
+        }
+    }
+
+    /// The bit of the leaf offset that selects the child at `depth`, 1 for the right child
+    fn direction(index: u64, depth: usize) -> u64 {
+        let offset = index + 1 - (1u64 << MERKLE_TREE_HEIGHT);
+        (offset >> (MERKLE_TREE_HEIGHT - 1 - depth)) & 1
+    }
+}
+
+impl HostBackend for MemoryBackend {
+    fn update_leaf(&mut self, root: [u8; 32], index: u64, data: [u8; 32]) -> [u8; 32] {
+        let mut siblings = Vec::with_capacity(MERKLE_TREE_HEIGHT);
+        let mut current = root;
+        for depth in 0..MERKLE_TREE_HEIGHT {
+            let (left, right) = self.children(&current, depth);
+            if Self::direction(index, depth) == 1 {
+                siblings.push(left);
+                current = right;
+            } else {
+                siblings.push(right);
+                current = left;
+            }
+        }
+
+        let mut hash = hash_leaf(&data);
+        self.leaves.insert(hash, data);
+        for depth in (0..MERKLE_TREE_HEIGHT).rev() {
+            let children = if Self::direction(index, depth) == 1 {
+                (siblings[depth], hash)
+            } else {
+                (hash, siblings[depth])
+            };
+            hash = hash_children(&children.0, &children.1);
+            self.nodes.insert(hash, children);
+        }
+        hash
+    }
+
+    fn get_leaf(&mut self, root: [u8; 32], index: u64) -> [u8; 32] {
+        let mut current = root;
+        for depth in 0..MERKLE_TREE_HEIGHT {
+            if current == self.defaults[depth] {
+                return [0; 32];
+            }
+            let (left, right) = self.children(&current, depth);
+            current = if Self::direction(index, depth) == 1 {
+                right
+            } else {
+                left
+            };
+        }
+        self.leaves.get(&current).cloned().unwrap_or([0; 32])
+    }
+
+    fn update_record(&mut self, hash: [u8; 32], data: Vec<u64>) {
+        self.records.insert(hash, data);
+    }
+
+    fn get_record(&mut self, hash: [u8; 32]) -> Vec<u64> {
+        self.records.get(&hash).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryBackend;
+    use crate::context::backend::HostBackend;
+    use crate::context::merkle::MERKLE_TREE_HEIGHT;
+
+    #[test]
+    fn test_memory_backend() {
+        // the empty root used by zkwasm_rest_abi::MERKLE_MAP
+        let empty_root = [
+            14789582351289948625u64,
+            10919489180071018470,
+            10309858136294505219,
+            2839580074036780766,
+        ]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<u8>>();
+        let mut backend = MemoryBackend::default();
+        assert_eq!(backend.empty_root().to_vec(), empty_root);
+
+        let first = (1u64 << MERKLE_TREE_HEIGHT) - 1;
+        let last = (1u64 << (MERKLE_TREE_HEIGHT + 1)) - 2;
+        let root0 = backend.empty_root();
+        let root1 = backend.update_leaf(root0, first + 3, [1; 32]);
+        let root2 = backend.update_leaf(root1, last, [2; 32]);
+        assert_eq!(backend.get_leaf(root2, first + 3), [1; 32]);
+        assert_eq!(backend.get_leaf(root2, last), [2; 32]);
+        assert_eq!(backend.get_leaf(root2, first), [0; 32]);
+        assert_eq!(backend.get_leaf(root1, last), [0; 32]);
+        // writing the same content in another order gives the same root
+        let root3 = backend.update_leaf(root0, last, [2; 32]);
+        assert_eq!(backend.update_leaf(root3, first + 3, [1; 32]), root2);
+        // clearing both leaves goes back to the empty root
+        let root4 = backend.update_leaf(root2, last, [0; 32]);
+        assert_eq!(backend.update_leaf(root4, first + 3, [0; 32]), root0);
+    }
+}
@@ -1,16 +1,14 @@
 use bytes_helper::Reduce;
 use bytes_helper::ReduceRule;
-use wasm_bindgen::prelude::wasm_bindgen;
 
-#[wasm_bindgen(raw_module = "./rpcbind.js")]
-extern "C" {
-    pub fn update_leaf(root: Vec<u8>, index: u64, leafdata: Vec<u8>) -> js_sys::Array; // root and leaf data is [u8; 32]
-    pub fn get_leaf(root: Vec<u8>, index: u64) -> js_sys::Array; // result needs to be [u8; 32]
-}
+use super::backend::{DefaultBackend, HostBackend};
+use super::lru::LeafCache;
 
 pub const MERKLE_TREE_HEIGHT: usize = 32;
+/// Number of recently touched leaves kept to avoid fetching them again from the backend
+pub const LEAF_CACHE_SIZE: usize = 1024;
 
-pub struct MerkleContext {
+pub struct MerkleContext<B: HostBackend = DefaultBackend> {
     pub k: u32,
     pub set_root: Reduce,
     pub get_root: Reduce,
@@ -21,14 +19,22 @@ pub struct MerkleContext {
     pub fetch: bool,
     pub root: [u8; 32],
     pub used_round: usize,
+    pub backend: B,
+    pub leaf_cache: LeafCache,
 }
 
 fn new_reduce(rules: Vec<ReduceRule>) -> Reduce {
     Reduce { cursor: 0, rules }
 }
 
-impl MerkleContext {
+impl<B: HostBackend + Default> MerkleContext<B> {
     pub fn new(k: u32) -> Self {
+        Self::with_backend(k, B::default())
+    }
+}
+
+impl<B: HostBackend> MerkleContext<B> {
+    pub fn with_backend(k: u32, backend: B) -> Self {
         MerkleContext {
             k,
             set_root: new_reduce(vec![ReduceRule::Bytes(vec![], 4)]),
@@ -40,6 +46,8 @@ impl MerkleContext {
             data_cursor: 0,
             root: [0; 32],
             used_round: 0,
+            backend,
+            leaf_cache: LeafCache::new(LEAF_CACHE_SIZE),
         }
     }
 
@@ -78,13 +86,9 @@ impl MerkleContext {
         if self.set.cursor == 0 {
             let address = self.address.rules[0].u64_value().unwrap() as u32;
             let index = (address as u64) + (1u64 << MERKLE_TREE_HEIGHT) - 1;
-            let hash = self.set.rules[0].bytes_value().unwrap();
-            self.root = update_leaf(self.root.to_vec(), index, hash)
-                    .into_iter()
-                    .map(|v| v.as_f64().unwrap() as u8)
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .unwrap();
+            let hash: [u8; 32] = self.set.rules[0].bytes_value().unwrap().try_into().unwrap();
+            self.root = self.backend.update_leaf(self.root, index, hash);
+            self.leaf_cache.insert(self.root, index, hash);
         }
     }
 
@@ -92,10 +96,14 @@ impl MerkleContext {
         let address = self.address.rules[0].u64_value().unwrap() as u32;
         let index = (address as u64) + (1u64 << MERKLE_TREE_HEIGHT) - 1;
         if self.data_cursor == 0 {
-            let leaf = get_leaf(self.root.to_vec(), index)
-                .into_iter()
-                .map(|v| v.as_f64().unwrap() as u8)
-                .collect::<Vec<_>>();
+            let leaf = match self.leaf_cache.get(&self.root, index) {
+                Some(leaf) => leaf,
+                None => {
+                    let leaf = self.backend.get_leaf(self.root, index);
+                    self.leaf_cache.insert(self.root, index, leaf);
+                    leaf
+                }
+            };
             let values = leaf
                 .chunks(8)
                 .into_iter()
@@ -108,5 +116,3 @@ impl MerkleContext {
         return v;
     }
 }
-
-impl MerkleContext {}
@@ -1,4 +1,9 @@
+pub mod backend;
 pub mod datacache;
 pub mod merkle;
 pub mod poseidon;
 pub mod jubjub;
+pub mod lru;
+pub mod memory;
+pub mod rpc;
+pub mod stats;
@@ -0,0 +1,50 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use super::backend::HostBackend;
+
+#[wasm_bindgen(raw_module = "./rpcbind.js")]
+extern "C" {
+    pub fn update_leaf(root: Vec<u8>, index: u64, leafdata: Vec<u8>) -> js_sys::Array; // root and leaf data is [u8; 32]
+    pub fn get_leaf(root: Vec<u8>, index: u64) -> js_sys::Array; // result needs to be [u8; 32]
+    pub fn update_record(hash: Vec<u8>, data: Vec<u64>);
+    pub fn get_record(hash: Vec<u8>) -> js_sys::BigUint64Array;
+}
+
+fn array_from_js_to_u8(js_array: js_sys::Array) -> [u8; 32] {
+    js_array
+        .into_iter()
+        .map(|v| v.as_f64().unwrap() as u8)
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap()
+}
+
+fn array_from_js_to_u64(js_array: js_sys::BigUint64Array) -> Vec<u64> {
+    let mut rust_vec = Vec::with_capacity(js_array.length() as usize);
+    for i in 0..js_array.length() {
+        rust_vec.push(js_array.get_index(i));
+    }
+    rust_vec
+}
+
+/// Backend that forwards every operation to the merkle db service through `rpcbind.js`
+#[derive(Default)]
+pub struct RpcBackend;
+
+impl HostBackend for RpcBackend {
+    fn update_leaf(&mut self, root: [u8; 32], index: u64, data: [u8; 32]) -> [u8; 32] {
+        array_from_js_to_u8(update_leaf(root.to_vec(), index, data.to_vec()))
+    }
+
+    fn get_leaf(&mut self, root: [u8; 32], index: u64) -> [u8; 32] {
+        array_from_js_to_u8(get_leaf(root.to_vec(), index))
+    }
+
+    fn update_record(&mut self, hash: [u8; 32], data: Vec<u64>) {
+        update_record(hash.to_vec(), data)
+    }
+
+    fn get_record(&mut self, hash: [u8; 32]) -> Vec<u64> {
+        array_from_js_to_u64(get_record(hash.to_vec()))
+    }
+}
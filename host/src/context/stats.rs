@@ -0,0 +1,96 @@
+use serde::Serialize;
+
+use super::backend::BackendTraffic;
+
+#[derive(Clone, Copy, Debug)]
+pub enum HostKind {
+    Merkle,
+    Poseidon,
+    Jubjub,
+    DataCache,
+}
+
+/// Usage of a single host context since the last reset
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ContextStats {
+    /// rounds as counted by the context (`used_round`), filled in when exported
+    pub rounds: usize,
+    /// host calls made by the guest
+    pub calls: u64,
+    /// operations forwarded to the storage backend (the merkle db service by default), filled in
+    /// when exported from the `Metered` backend of the merkle and data cache contexts
+    pub backend_calls: u64,
+    /// payload bytes sent to the storage backend
+    pub bytes_sent: u64,
+    /// payload bytes received from the storage backend
+    pub bytes_received: u64,
+    /// cumulative time spent in the host calls, in milliseconds
+    pub time_ms: f64,
+}
+
+impl ContextStats {
+    pub fn set_traffic(&mut self, traffic: &BackendTraffic) {
+        self.backend_calls = traffic.calls;
+        self.bytes_sent = traffic.bytes_sent;
+        self.bytes_received = traffic.bytes_received;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LeafCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct HostStats {
+    pub merkle: ContextStats,
+    pub poseidon: ContextStats,
+    pub jubjub: ContextStats,
+    pub datacache: ContextStats,
+    pub leaf_cache: LeafCacheStats,
+}
+
+impl HostStats {
+    pub fn context_mut(&mut self, kind: HostKind) -> &mut ContextStats {
+        match kind {
+            HostKind::Merkle => &mut self.merkle,
+            HostKind::Poseidon => &mut self.poseidon,
+            HostKind::Jubjub => &mut self.jubjub,
+            HostKind::DataCache => &mut self.datacache,
+        }
+    }
+
+    pub fn record(&mut self, kind: HostKind, time_ms: f64) {
+        let stats = self.context_mut(kind);
+        stats.calls += 1;
+        stats.time_ms += time_ms;
+    }
+}
+
+/// Milliseconds from an arbitrary origin, only differences are meaningful. Host calls take well
+/// under a millisecond, so the sub-millisecond `performance.now()` of the global scope (window,
+/// worker or node) is used, `Date.now()` only when it is missing.
+#[cfg(target_arch = "wasm32")]
+pub fn now_ms() -> f64 {
+    use wasm_bindgen::JsCast;
+    thread_local! {
+        static PERFORMANCE: Option<web_sys::Performance> =
+            js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+                .ok()
+                .filter(|p| p.is_object())
+                .map(|p| p.unchecked_into::<web_sys::Performance>());
+    }
+    PERFORMANCE.with(|performance| match performance {
+        Some(performance) => performance.now(),
+        None => js_sys::Date::now(),
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> f64 {
+    lazy_static::lazy_static! {
+        static ref START: std::time::Instant = std::time::Instant::now();
+    }
+    START.elapsed().as_secs_f64() * 1000.0
+}
@@ -1,5 +1,6 @@
 use std::sync::Mutex;
 use context::{datacache::CacheContext, jubjub::sum::BabyJubjubSumContext, merkle::MerkleContext, poseidon::PoseidonContext};
+use context::stats::{now_ms, HostKind, HostStats};
 use js_sys::BigUint64Array;
 use wasm_bindgen::prelude::*;
 
@@ -13,86 +14,163 @@ lazy_static::lazy_static! {
     pub static ref MERKLE_CONTEXT: Mutex<MerkleContext> = Mutex::new(MerkleContext::new(0));
     pub static ref POSEIDON_CONTEXT: Mutex<PoseidonContext> = Mutex::new(PoseidonContext::default(0));
     pub static ref JUBJUB_CONTEXT: Mutex<BabyJubjubSumContext> = Mutex::new(BabyJubjubSumContext::default(0));
+    pub static ref HOST_STATS: Mutex<HostStats> = Mutex::new(HostStats::default());
+}
+
+/// Run a host call and account its time to the context of `kind`
+fn track<R>(kind: HostKind, f: impl FnOnce() -> R) -> R {
+    let start = now_ms();
+    let r = f();
+    HOST_STATS.lock().unwrap().record(kind, now_ms() - start);
+    r
+}
+
+/// Usage of every host context since the last reset as JSON
+#[wasm_bindgen]
+pub fn host_stats() -> String {
+    let mut stats = HOST_STATS.lock().unwrap().clone();
+    {
+        let merkle = MERKLE_CONTEXT.lock().unwrap();
+        stats.merkle.rounds = merkle.used_round;
+        stats.leaf_cache.hits = merkle.leaf_cache.hits;
+        stats.leaf_cache.misses = merkle.leaf_cache.misses;
+        stats.merkle.set_traffic(&merkle.backend.traffic);
+    }
+    {
+        let datacache = DATACACHE_CONTEXT.lock().unwrap();
+        stats.datacache.rounds = datacache.used_round;
+        stats.datacache.set_traffic(&datacache.backend.traffic);
+    }
+    stats.poseidon.rounds = POSEIDON_CONTEXT.lock().unwrap().used_round;
+    stats.jubjub.rounds = JUBJUB_CONTEXT.lock().unwrap().used_round;
+    serde_json::to_string(&stats).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn reset_host_stats() {
+    *HOST_STATS.lock().unwrap() = HostStats::default();
+    {
+        let mut merkle = MERKLE_CONTEXT.lock().unwrap();
+        merkle.used_round = 0;
+        merkle.leaf_cache.hits = 0;
+        merkle.leaf_cache.misses = 0;
+        merkle.backend.traffic = Default::default();
+    }
+    {
+        let mut datacache = DATACACHE_CONTEXT.lock().unwrap();
+        datacache.used_round = 0;
+        datacache.backend.traffic = Default::default();
+    }
+    POSEIDON_CONTEXT.lock().unwrap().used_round = 0;
+    JUBJUB_CONTEXT.lock().unwrap().used_round = 0;
 }
 
 
 #[wasm_bindgen]
 pub fn cache_set_mode(mode: u64) {
-    DATACACHE_CONTEXT.lock().unwrap().set_mode(mode);
+    track(HostKind::DataCache, || DATACACHE_CONTEXT.lock().unwrap().set_mode(mode))
 }
 
 #[wasm_bindgen]
 pub fn cache_set_hash(arg: u64) {
-    DATACACHE_CONTEXT.lock().unwrap().set_data_hash(arg);
+    track(HostKind::DataCache, || DATACACHE_CONTEXT.lock().unwrap().set_data_hash(arg))
 }
 
 #[wasm_bindgen]
 pub fn cache_store_data(data: u64) {
-    DATACACHE_CONTEXT.lock().unwrap().store_data(data);
+    track(HostKind::DataCache, || DATACACHE_CONTEXT.lock().unwrap().store_data(data))
 }
 
 #[wasm_bindgen]
 pub fn cache_fetch_data() -> u64 {
-    DATACACHE_CONTEXT.lock().unwrap().fetch_data()
+    track(HostKind::DataCache, || DATACACHE_CONTEXT.lock().unwrap().fetch_data())
 }
 
 #[wasm_bindgen]
 pub fn poseidon_new(arg: u64) {
-    POSEIDON_CONTEXT.lock().unwrap().poseidon_new(arg as usize);
+    track(HostKind::Poseidon, || POSEIDON_CONTEXT.lock().unwrap().poseidon_new(arg as usize))
 }
 
 #[wasm_bindgen]
 pub fn poseidon_push(arg: u64) {
-    POSEIDON_CONTEXT.lock().unwrap().poseidon_push(arg);
+    track(HostKind::Poseidon, || POSEIDON_CONTEXT.lock().unwrap().poseidon_push(arg))
 }
 
 #[wasm_bindgen]
 pub fn poseidon_finalize() -> u64 {
-    POSEIDON_CONTEXT.lock().unwrap().poseidon_finalize()
+    track(HostKind::Poseidon, || POSEIDON_CONTEXT.lock().unwrap().poseidon_finalize())
 }
 
 #[wasm_bindgen]
 pub fn babyjubjub_sum_new(arg: u64) {
-    JUBJUB_CONTEXT.lock().unwrap().babyjubjub_sum_new(arg as usize);
+    track(HostKind::Jubjub, || JUBJUB_CONTEXT.lock().unwrap().babyjubjub_sum_new(arg as usize))
 }
 
 #[wasm_bindgen]
 pub fn babyjubjub_sum_push(arg: u64) {
-    JUBJUB_CONTEXT.lock().unwrap().babyjubjub_sum_push(arg);
+    track(HostKind::Jubjub, || JUBJUB_CONTEXT.lock().unwrap().babyjubjub_sum_push(arg))
 }
 
 #[wasm_bindgen]
 pub fn babyjubjub_sum_finalize() -> u64 {
-    JUBJUB_CONTEXT.lock().unwrap().babyjubjub_sum_finalize()
+    track(HostKind::Jubjub, || JUBJUB_CONTEXT.lock().unwrap().babyjubjub_sum_finalize())
 }
 
 #[wasm_bindgen]
 pub fn merkle_setroot(arg: u64) {
-    MERKLE_CONTEXT.lock().unwrap().merkle_setroot(arg);
+    track(HostKind::Merkle, || MERKLE_CONTEXT.lock().unwrap().merkle_setroot(arg))
 }
 
 #[wasm_bindgen]
 pub fn merkle_getroot() -> u64 {
-    MERKLE_CONTEXT.lock().unwrap().merkle_getroot()
+    track(HostKind::Merkle, || MERKLE_CONTEXT.lock().unwrap().merkle_getroot())
 }
 
 #[wasm_bindgen]
 pub fn merkle_address(arg: u64) {
-    MERKLE_CONTEXT.lock().unwrap().merkle_address(arg);
+    track(HostKind::Merkle, || MERKLE_CONTEXT.lock().unwrap().merkle_address(arg))
 }
 
 #[wasm_bindgen]
 pub fn merkle_set(arg: u64) {
-    MERKLE_CONTEXT.lock().unwrap().merkle_set(arg);
+    track(HostKind::Merkle, || MERKLE_CONTEXT.lock().unwrap().merkle_set(arg))
 }
 
 #[wasm_bindgen]
 pub fn merkle_get() -> u64 {
-    MERKLE_CONTEXT.lock().unwrap().merkle_get()
+    track(HostKind::Merkle, || MERKLE_CONTEXT.lock().unwrap().merkle_get())
 }
 
 
 #[wasm_bindgen]
 pub fn check() -> BigUint64Array {
-    crate::context::datacache::get_record([10;32].to_vec())
+    crate::context::rpc::get_record([10;32].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host_stats, poseidon_finalize, poseidon_new, poseidon_push, reset_host_stats};
+
+    #[test]
+    fn test_host_stats() {
+        reset_host_stats();
+        // hash 8 field elements of 4 words
+        poseidon_new(1);
+        for _ in 0..32 {
+            poseidon_push(1);
+        }
+        for _ in 0..4 {
+            poseidon_finalize();
+        }
+        let stats: serde_json::Value = serde_json::from_str(&host_stats()).unwrap();
+        assert_eq!(stats["poseidon"]["calls"], 37);
+        assert_eq!(stats["poseidon"]["rounds"], 1);
+        assert_eq!(stats["merkle"]["calls"], 0);
+        assert_eq!(stats["datacache"]["bytes_sent"], 0);
+
+        reset_host_stats();
+        let stats: serde_json::Value = serde_json::from_str(&host_stats()).unwrap();
+        assert_eq!(stats["poseidon"]["calls"], 0);
+        assert_eq!(stats["poseidon"]["rounds"], 0);
+    }
 }